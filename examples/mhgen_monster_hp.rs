@@ -2,14 +2,12 @@
 // monster's health to 1000, then displays the monster's health every second until its health
 // reaches 0.
 
-extern crate ntr;
-
 use ntr::Connection;
 use std::thread;
 use std::time::Duration;
 
 // ip of N3DS to connect to
-const N3DS_IP: &'static str = "192.168.2.210";
+const N3DS_IP: &str = "192.168.2.210";
 
 // title id for Monster Hunter Generations (USA); list can be found at http://3dsdb.com/
 const MH_TID: u64 = 0x0004000000187000;
@@ -20,26 +18,23 @@ const HEALTH_OFFSET: u32 = 0x1318;
 
 fn main() {
     println!("Connecting to {}", N3DS_IP);
-    let mut connection = Connection::new(N3DS_IP).unwrap();
+    let connection = Connection::new(N3DS_IP).unwrap();
     print!("Connected.\n\n");
 
-    // get process id using title id
-    let pid = connection
-        .get_pid(MH_TID)
-        .expect("io error")
-        .expect("pid not found");
+    // look up the game's process using its title id
+    let game = connection.attach_by_tid(MH_TID).expect("pid not found");
 
-    // go through a pointer to get the health address
-    let health_address = connection.read_u32(MONSTER_1_PTR, pid).unwrap() + HEALTH_OFFSET;
-    let initial_health = connection.read_u32(health_address, pid).unwrap();
-    println!("Health address: {:x}\nInitial health: {}", health_address, initial_health);
+    // the health is found by going through a pointer to the monster
+    let health_ptr_chain = &[HEALTH_OFFSET];
+    let initial_health = game.read_u32_via(MONSTER_1_PTR, health_ptr_chain).unwrap();
+    println!("Initial health: {}", initial_health);
 
     // set monster's health to 1000
-    connection.write_u32(health_address, 1000, pid).unwrap();
+    game.write_u32_via(MONSTER_1_PTR, health_ptr_chain, 1000).unwrap();
 
     // monster health printing
     loop {
-        let health = connection.read_u32(health_address, pid).unwrap();
+        let health = game.read_u32_via(MONSTER_1_PTR, health_ptr_chain).unwrap();
         if health > 0 {
             println!("First monster's health: {}\n", health);
             thread::sleep(Duration::from_secs(1));
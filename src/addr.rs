@@ -0,0 +1,286 @@
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6, TcpStream,
+               ToSocketAddrs};
+use std::time::Duration;
+use crate::DEFAULT_PORT;
+
+/// An address a connection can be opened to.
+///
+/// This is implemented for host names and IP addresses as strings, with or without a port, as
+/// well as for the `std::net` address types and `(host, port)` tuples. When the address doesn't
+/// include a port, the NTR debugger port (8000) is used. All of these work:
+///
+/// ```no_run
+/// use ntr::Connection;
+/// use std::net::{Ipv4Addr, SocketAddr};
+///
+/// # fn main() -> ntr::Result<()> {
+/// Connection::new("192.168.2.247")?;
+/// Connection::new("192.168.2.247:8000")?;
+/// Connection::new("fe80::1")?;
+/// Connection::new("[fe80::1]:8000")?;
+/// Connection::new("my-3ds.local")?;
+/// Connection::new(Ipv4Addr::new(192, 168, 2, 247))?;
+/// Connection::new((Ipv4Addr::new(192, 168, 2, 247), 8000))?;
+/// Connection::new("192.168.2.247:8000".parse::<SocketAddr>().unwrap())?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// The trait is sealed, so it can't be implemented outside this crate.
+pub trait ToAddr: Sealed {}
+
+impl<T: Sealed + ?Sized> ToAddr for T {}
+
+pub(crate) mod private {
+    use std::net::{IpAddr, SocketAddr};
+
+    /// Keeps `ToAddr` from being implemented outside this crate.
+    pub trait Sealed {
+        /// Returns the address as a `Target`.
+        fn target(&self) -> Target;
+    }
+
+    /// An address as it was given, before the default port is filled in.
+    #[derive(Clone, Debug)]
+    pub enum Target {
+        /// A host name, with the port if one was given.
+        Host(String, Option<u16>),
+        /// An IP address without a port.
+        Ip(IpAddr),
+        /// A socket address, which includes the port.
+        Socket(SocketAddr),
+    }
+}
+
+use self::private::{Sealed, Target};
+
+/// Where a connection is opened to, once the port has been settled.
+///
+/// Host names are kept as they are rather than resolved up front, so they're looked up again
+/// by every reconnect.
+#[derive(Clone, Debug)]
+pub(crate) enum Endpoint {
+    Host(String, u16),
+    Socket(SocketAddr),
+}
+
+impl Endpoint {
+    /// Opens a TCP connection, giving up on each address after `timeout` if it's set.
+    ///
+    /// A host name can resolve to several addresses; they're tried in turn, and the last
+    /// address's error is returned if none of them works.
+    pub(crate) fn connect(&self, timeout: Option<Duration>) -> io::Result<TcpStream> {
+        let timeout = match timeout {
+            Some(timeout) => timeout,
+            None => {
+                return match *self {
+                    Endpoint::Host(ref host, port) => TcpStream::connect((host as &str, port)),
+                    Endpoint::Socket(addr) => TcpStream::connect(addr),
+                };
+            }
+        };
+        let addrs = match *self {
+            Endpoint::Host(ref host, port) => (host as &str, port).to_socket_addrs()?.collect(),
+            Endpoint::Socket(addr) => vec![addr],
+        };
+        let mut last_err = io::Error::new(io::ErrorKind::InvalidInput,
+                                          "could not resolve to any addresses");
+        for addr in addrs {
+            match TcpStream::connect_timeout(&addr, timeout) {
+                Ok(stream) => return Ok(stream),
+                Err(err) => last_err = err,
+            }
+        }
+        Err(last_err)
+    }
+}
+
+impl Target {
+    /// Settles on the port to use: `port` if it's set, otherwise the address's own port,
+    /// otherwise the default.
+    pub(crate) fn endpoint(&self, port: Option<u16>) -> Endpoint {
+        match *self {
+            Target::Host(ref host, host_port) => {
+                Endpoint::Host(host.clone(), port.or(host_port).unwrap_or(DEFAULT_PORT))
+            }
+            Target::Ip(ip) => Endpoint::Socket(SocketAddr::new(ip, port.unwrap_or(DEFAULT_PORT))),
+            Target::Socket(mut addr) => {
+                if let Some(port) = port {
+                    // set_port rather than rebuilding the address keeps an IPv6 scope id
+                    addr.set_port(port);
+                }
+                Endpoint::Socket(addr)
+            }
+        }
+    }
+}
+
+/// Parses an address given as a string.
+///
+/// A port is only split off when what's left can't be an IPv6 address, so `fe80::1` is an
+/// address with no port while `[fe80::1]:8000` and `localhost:8000` have one.
+fn parse_target(s: &str) -> Target {
+    if let Ok(addr) = s.parse() {
+        return Target::Socket(addr);
+    }
+    let unbracketed = s.strip_prefix('[').and_then(|s| s.strip_suffix(']')).unwrap_or(s);
+    if let Ok(ip) = unbracketed.parse() {
+        return Target::Ip(ip);
+    }
+    if let Some((host, port)) = s.rsplit_once(':') {
+        if let (false, Ok(port)) = (host.contains(':'), port.parse()) {
+            return Target::Host(host.to_owned(), Some(port));
+        }
+    }
+    Target::Host(s.to_owned(), None)
+}
+
+/// Builds the target for a host, given as a string, and a separate port.
+fn host_port_target(host: &str, port: u16) -> Target {
+    let unbracketed = host.strip_prefix('[').and_then(|s| s.strip_suffix(']')).unwrap_or(host);
+    match unbracketed.parse() {
+        Ok(ip) => Target::Socket(SocketAddr::new(ip, port)),
+        Err(_) => Target::Host(host.to_owned(), Some(port)),
+    }
+}
+
+impl Sealed for str {
+    fn target(&self) -> Target {
+        parse_target(self)
+    }
+}
+
+impl Sealed for String {
+    fn target(&self) -> Target {
+        parse_target(self)
+    }
+}
+
+impl<T: Sealed + ?Sized> Sealed for &T {
+    fn target(&self) -> Target {
+        (**self).target()
+    }
+}
+
+impl Sealed for IpAddr {
+    fn target(&self) -> Target {
+        Target::Ip(*self)
+    }
+}
+
+impl Sealed for Ipv4Addr {
+    fn target(&self) -> Target {
+        Target::Ip(IpAddr::V4(*self))
+    }
+}
+
+impl Sealed for Ipv6Addr {
+    fn target(&self) -> Target {
+        Target::Ip(IpAddr::V6(*self))
+    }
+}
+
+impl Sealed for SocketAddr {
+    fn target(&self) -> Target {
+        Target::Socket(*self)
+    }
+}
+
+impl Sealed for SocketAddrV4 {
+    fn target(&self) -> Target {
+        Target::Socket(SocketAddr::V4(*self))
+    }
+}
+
+impl Sealed for SocketAddrV6 {
+    fn target(&self) -> Target {
+        Target::Socket(SocketAddr::V6(*self))
+    }
+}
+
+impl Sealed for (&str, u16) {
+    fn target(&self) -> Target {
+        host_port_target(self.0, self.1)
+    }
+}
+
+impl Sealed for (String, u16) {
+    fn target(&self) -> Target {
+        host_port_target(&self.0, self.1)
+    }
+}
+
+impl Sealed for (IpAddr, u16) {
+    fn target(&self) -> Target {
+        Target::Socket(SocketAddr::new(self.0, self.1))
+    }
+}
+
+impl Sealed for (Ipv4Addr, u16) {
+    fn target(&self) -> Target {
+        Target::Socket(SocketAddr::new(IpAddr::V4(self.0), self.1))
+    }
+}
+
+impl Sealed for (Ipv6Addr, u16) {
+    fn target(&self) -> Target {
+        Target::Socket(SocketAddr::new(IpAddr::V6(self.0), self.1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Settles the port for `s`, returning the host or IP address and the port.
+    fn endpoint(s: &str, port: Option<u16>) -> (String, u16) {
+        match s.target().endpoint(port) {
+            Endpoint::Host(host, port) => (host, port),
+            Endpoint::Socket(addr) => (addr.ip().to_string(), addr.port()),
+        }
+    }
+
+    #[test]
+    fn bare_ipv6_has_no_port() {
+        assert!(matches!(parse_target("fe80::1"), Target::Ip(IpAddr::V6(_))));
+        assert_eq!(endpoint("fe80::1", None), ("fe80::1".to_owned(), DEFAULT_PORT));
+    }
+
+    #[test]
+    fn bracketed_ipv6() {
+        assert!(matches!(parse_target("[fe80::1]:8001"), Target::Socket(_)));
+        assert_eq!(endpoint("[fe80::1]:8001", None), ("fe80::1".to_owned(), 8001));
+        assert_eq!(endpoint("[fe80::1]", None), ("fe80::1".to_owned(), DEFAULT_PORT));
+    }
+
+    #[test]
+    fn host_with_port() {
+        assert!(matches!(parse_target("host:8001"),
+                         Target::Host(ref host, Some(8001)) if host == "host"));
+        assert_eq!(endpoint("host", None), ("host".to_owned(), DEFAULT_PORT));
+    }
+
+    #[test]
+    fn ipv4() {
+        assert!(matches!(parse_target("1.2.3.4"), Target::Ip(IpAddr::V4(_))));
+        assert_eq!(endpoint("1.2.3.4", None), ("1.2.3.4".to_owned(), DEFAULT_PORT));
+        assert_eq!(endpoint("1.2.3.4:8001", None), ("1.2.3.4".to_owned(), 8001));
+    }
+
+    #[test]
+    fn explicit_port_overrides_port_in_string() {
+        assert_eq!(endpoint("host:8001", Some(9000)), ("host".to_owned(), 9000));
+        assert_eq!(endpoint("1.2.3.4:8001", Some(9000)), ("1.2.3.4".to_owned(), 9000));
+        assert_eq!(endpoint("[fe80::1]:8001", Some(9000)), ("fe80::1".to_owned(), 9000));
+        assert_eq!(endpoint("fe80::1", Some(9000)), ("fe80::1".to_owned(), 9000));
+    }
+
+    #[test]
+    fn host_and_separate_port() {
+        assert!(matches!(host_port_target("[fe80::1]", 8001), Target::Socket(_)));
+        assert!(matches!(host_port_target("1.2.3.4", 8001), Target::Socket(_)));
+        assert!(matches!(host_port_target("host", 8001),
+                         Target::Host(ref host, Some(8001)) if host == "host"));
+    }
+}
@@ -0,0 +1,449 @@
+use crate::addr::Endpoint;
+use crate::link::{check_size, validate_range};
+use crate::packet::{HEADER_LEN, encode_header};
+use crate::process::{OutputBuffer, PROCESS_LIST_END, parse_process_list};
+use crate::{ConnectionBuilder, Error, Packet, Pid, ProcessInfo, Result, ToAddr};
+use crate::{MIN_POLL_INTERVAL_MS, handshake_error};
+use byteorder::{ByteOrder, LittleEndian};
+use std::cmp;
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex as StdMutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::sync::{Mutex, mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tokio::time;
+
+/// Senders for the requests waiting on a response, keyed by sequence number.
+type PendingResponses = Arc<StdMutex<HashMap<u32, oneshot::Sender<Packet>>>>;
+
+/// Hands packets to the task that writes them to the socket, numbering them as it goes.
+///
+/// Packets are queued whole and written by a single task, so dropping a future partway through
+/// a call can't leave half a packet on the socket.
+#[derive(Debug)]
+struct Writer {
+    tx: mpsc::UnboundedSender<Vec<u8>>,
+    seq: u32,
+}
+
+impl Writer {
+    /// Queues a packet, returning the sequence number it was given.
+    fn send(&mut self, packet_type: u32, cmd: u32, args: &[u32; 16], data: &[u8]) -> Result<u32> {
+        let seq = self.seq;
+        let mut packet = encode_header(seq, packet_type, cmd, args, data.len() as u32).to_vec();
+        packet.extend_from_slice(data);
+        self.tx.send(packet).map_err(|_| Error::Disconnected)?;
+        self.seq = self.seq.wrapping_add(1000);
+        Ok(seq)
+    }
+}
+
+/// A request that has been sent but not answered yet.
+///
+/// Dropping it unregisters the request, so a response that arrives later is discarded.
+struct PendingResponse {
+    seq: u32,
+    rx: oneshot::Receiver<Packet>,
+    pending: PendingResponses,
+}
+
+impl PendingResponse {
+    /// Waits up to `timeout` for the response.
+    async fn wait(&mut self, timeout: Duration) -> Result<Packet> {
+        match time::timeout(timeout, &mut self.rx).await {
+            Ok(Ok(packet)) => Ok(packet),
+            Ok(Err(_)) => Err(Error::Disconnected),
+            Err(_) => Err(Error::Timeout),
+        }
+    }
+}
+
+impl Drop for PendingResponse {
+    fn drop(&mut self) {
+        self.pending.lock().unwrap().remove(&self.seq);
+    }
+}
+
+/// A connection to a 3DS for use from async code, built on Tokio.
+///
+/// This offers the core of [`Connection`](struct.Connection.html)'s API as `async fn`s, so it
+/// can be used from an async runtime without tying up a thread per call. Responses are matched
+/// to requests by sequence number like they are for `Connection`; heartbeats and incoming
+/// packets are handled by tasks spawned on the current Tokio runtime, which stop when the
+/// connection is dropped. It's only available with the `tokio` feature enabled.
+///
+/// The futures can be dropped at any point, for example by `tokio::time::timeout` or
+/// `select!`, without corrupting the connection: a call's packets are handed to the task that
+/// writes them before its first `.await`, so each is sent whole or not at all. A dropped read
+/// just discards its response when it arrives.
+///
+/// # Examples
+///
+/// ```no_run
+/// use ntr::AsyncConnection;
+///
+/// async fn double_gold() -> ntr::Result<()> {
+///     let connection = AsyncConnection::new("192.168.2.247").await?;
+///     let pid = connection.get_pid(0x0004000000187000).await?;
+///     let gold = connection.read_u32(0x8001000, pid).await?;
+///     connection.write_u32(0x8001000, gold * 2, pid).await
+/// }
+/// ```
+#[derive(Debug)]
+pub struct AsyncConnection {
+    writer: Arc<StdMutex<Writer>>,
+    connected: Arc<AtomicBool>,
+    pending: PendingResponses,
+    process_list_rx: Mutex<mpsc::UnboundedReceiver<String>>,
+    read_timeout: Duration,
+    validate_addresses: bool,
+    chunk_len: u32,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl AsyncConnection {
+    /// Opens a connection to the 3DS with the address `addr`.
+    ///
+    /// The address takes the same forms as for `Connection::new`; see
+    /// [`ToAddr`](trait.ToAddr.html). This must be called from within a Tokio runtime.
+    pub async fn new<A: ToAddr>(addr: A) -> Result<Self> {
+        ConnectionBuilder::new(addr).connect_async().await
+    }
+
+    /// Opens a connection to the 3DS with the address `addr`, on port `port` instead of 8000 or
+    /// any port in `addr`.
+    ///
+    /// This must be called from within a Tokio runtime.
+    pub async fn with_port<A: ToAddr>(addr: A, port: u16) -> Result<Self> {
+        ConnectionBuilder::new(addr).port(port).connect_async().await
+    }
+
+    pub(crate) async fn connect(options: &ConnectionBuilder) -> Result<Self> {
+        let endpoint = options.target.endpoint(options.port);
+        let mut tcp_stream = match options.connect_timeout {
+            Some(timeout) => {
+                time::timeout(timeout, connect_endpoint(&endpoint))
+                    .await
+                    .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??
+            }
+            None => connect_endpoint(&endpoint).await?,
+        };
+        let mut seq = options.initial_seq;
+        if options.handshake {
+            match time::timeout(options.read_timeout,
+                                handshake(&mut tcp_stream, seq, options.max_data_len))
+                      .await {
+                Ok(result) => result?,
+                Err(_) => return Err(Error::Timeout),
+            }
+            seq = seq.wrapping_add(1000);
+        }
+
+        let (read_half, write_half) = tcp_stream.into_split();
+        let (write_tx, write_rx) = mpsc::unbounded_channel();
+        let writer = Arc::new(StdMutex::new(Writer { tx: write_tx, seq }));
+        let connected = Arc::new(AtomicBool::new(true));
+        let pending: PendingResponses = Arc::new(StdMutex::new(HashMap::new()));
+        let heartbeat_sendable = Arc::new(AtomicBool::new(true));
+        let (process_list_tx, process_list_rx) = mpsc::unbounded_channel();
+
+        let mut tasks = Vec::new();
+        tasks.push(tokio::spawn(write(write_half, write_rx, connected.clone())));
+        tasks.push(tokio::spawn(receive(read_half,
+                                        connected.clone(),
+                                        pending.clone(),
+                                        heartbeat_sendable.clone(),
+                                        process_list_tx,
+                                        options.max_data_len as usize)));
+        if let Some(interval) = options.heartbeat_interval {
+            tasks.push(tokio::spawn(heartbeat(writer.clone(),
+                                              connected.clone(),
+                                              heartbeat_sendable,
+                                              interval)));
+        }
+
+        Ok(AsyncConnection {
+               writer,
+               connected,
+               pending,
+               process_list_rx: Mutex::new(process_list_rx),
+               read_timeout: options.read_timeout,
+               validate_addresses: options.validate_addresses,
+               chunk_len: options.chunk_len,
+               tasks,
+           })
+    }
+
+    /// Returns how long reads wait for a response before giving up.
+    pub fn read_timeout(&self) -> Duration {
+        self.read_timeout
+    }
+
+    /// Sets how long reads wait for a response before giving up. The default is 10 seconds.
+    pub fn set_read_timeout(&mut self, timeout: Duration) {
+        self.read_timeout = timeout;
+    }
+
+    /// Returns every process currently running on the 3DS, waiting up to the
+    /// [`read_timeout`](#method.read_timeout) for the list.
+    ///
+    /// See [`Connection::list_processes`](struct.Connection.html#method.list_processes).
+    pub async fn list_processes(&self) -> Result<Vec<ProcessInfo>> {
+        self.check_connected()?;
+
+        // held until the list arrives, so concurrent calls don't take each other's lists
+        let mut process_list_rx = self.process_list_rx.lock().await;
+        while process_list_rx.try_recv().is_ok() {}
+        self.writer
+            .lock()
+            .unwrap()
+            .send(0, 5, &[0u32; 16], &[])?;
+        match time::timeout(self.read_timeout, process_list_rx.recv()).await {
+            Ok(Some(msg)) => Ok(parse_process_list(&msg)),
+            Ok(None) => Err(Error::Disconnected),
+            Err(_) => Err(Error::Timeout),
+        }
+    }
+
+    /// Returns the process identifier for the currently running title id `tid`.
+    ///
+    /// Returns `Error::PidNotFound` if no running process has that title id.
+    pub async fn get_pid(&self, tid: u64) -> Result<Pid> {
+        self.list_processes()
+            .await?
+            .into_iter()
+            .find(|process| process.tid == tid)
+            .map(|process| process.pid)
+            .ok_or(Error::PidNotFound)
+    }
+
+    /// Reads a chunk of 3DS memory.
+    ///
+    /// Reads `size` bytes starting from address `addr` for the process with process id `pid`,
+    /// waiting at most the connection's [`read_timeout`](#method.read_timeout) for each chunk
+    /// (64 KiB unless the builder's `chunk_size` says otherwise). Unless address validation was
+    /// turned off in the builder, an address or size of zero is rejected before anything is
+    /// sent. A chunk NTR answers with no data fails with `Error::EmptyResponse` rather than
+    /// being retried.
+    pub async fn mem_read(&self, addr: u32, size: u32, pid: Pid) -> Result<Vec<u8>> {
+        self.validate(addr, size)?;
+
+        // every chunk is requested before any response is waited on
+        let mut responses = Vec::new();
+        {
+            let mut writer = self.writer.lock().unwrap();
+            let mut offset = 0;
+            while offset < size {
+                let chunk_len = cmp::min(self.chunk_len, size - offset);
+                let mut args = [0u32; 16];
+                args[0] = pid.0;
+                args[1] = addr.wrapping_add(offset);
+                args[2] = chunk_len;
+                responses.push((self.register_response(writer.seq)?, chunk_len));
+                writer.send(0, 9, &args, &[])?;
+                offset += chunk_len;
+            }
+        }
+
+        let mut data = Vec::with_capacity(size as usize);
+        for (mut response, chunk_len) in responses {
+            let chunk = check_size(chunk_len, response.wait(self.read_timeout).await?.data)?;
+            data.extend_from_slice(&chunk);
+        }
+        Ok(data)
+    }
+
+    /// Writes data to 3DS memory.
+    ///
+    /// Writes `data` to the 3DS memory starting at address `addr` for the process with process id
+    /// `pid`, in packets of at most the builder's `chunk_size`. Returns `data.len()`. As with
+    /// [`Connection::mem_write`](struct.Connection.html#method.mem_write), NTR doesn't
+    /// acknowledge writes, so success only means the request was queued to be sent.
+    pub async fn mem_write(&self, addr: u32, data: &[u8], pid: Pid) -> Result<usize> {
+        if data.len() > u32::MAX as usize {
+            return Err(Error::Io(io::Error::new(io::ErrorKind::InvalidInput,
+                                                "data is larger than the address space")));
+        }
+        self.validate(addr, data.len() as u32)?;
+        self.check_connected()?;
+        let mut writer = self.writer.lock().unwrap();
+        let mut written = 0;
+        for chunk in data.chunks(self.chunk_len as usize) {
+            let mut args = [0u32; 16];
+            args[0] = pid.0;
+            args[1] = addr.wrapping_add(written as u32);
+            args[2] = chunk.len() as u32;
+            writer.send(1, 10, &args, chunk)?;
+            written += chunk.len();
+        }
+        Ok(written)
+    }
+
+    /// Reads a `u32` from 3DS memory.
+    pub async fn read_u32(&self, addr: u32, pid: Pid) -> Result<u32> {
+        Ok(LittleEndian::read_u32(&self.mem_read(addr, 4, pid).await?))
+    }
+
+    /// Writes a `u32` to 3DS memory.
+    pub async fn write_u32(&self, addr: u32, data: u32, pid: Pid) -> Result<()> {
+        let buf = &mut [0u8; 4];
+        LittleEndian::write_u32(buf, data);
+        self.mem_write(addr, buf, pid).await.map(|_| ())
+    }
+
+    /// Registers to receive the response to the packet with sequence number `seq`.
+    fn register_response(&self, seq: u32) -> Result<PendingResponse> {
+        let (tx, rx) = oneshot::channel();
+        self.pending.lock().unwrap().insert(seq, tx);
+        let response = PendingResponse {
+            seq,
+            rx,
+            pending: self.pending.clone(),
+        };
+
+        // checked after registering, so a receiver task that exits concurrently has either
+        // cleared our entry or already marked the connection as closed
+        self.check_connected()?;
+        Ok(response)
+    }
+
+    fn validate(&self, addr: u32, size: u32) -> Result<()> {
+        if self.validate_addresses {
+            validate_range(addr, size)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn check_connected(&self) -> Result<()> {
+        if self.connected.load(Ordering::SeqCst) {
+            Ok(())
+        } else {
+            Err(Error::Disconnected)
+        }
+    }
+}
+
+impl Drop for AsyncConnection {
+    /// Stops the background tasks, which closes the socket.
+    fn drop(&mut self) {
+        self.connected.store(false, Ordering::SeqCst);
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+/// Opens a TCP connection to `endpoint`.
+async fn connect_endpoint(endpoint: &Endpoint) -> io::Result<TcpStream> {
+    match *endpoint {
+        Endpoint::Host(ref host, port) => TcpStream::connect((host as &str, port)).await,
+        Endpoint::Socket(addr) => TcpStream::connect(addr).await,
+    }
+}
+
+/// Sends a heartbeat with sequence number `seq` and checks that the reply is an NTR packet,
+/// before the socket is handed to the background tasks.
+async fn handshake(stream: &mut TcpStream, seq: u32, max_data_len: u32) -> Result<()> {
+    stream.write_all(&encode_header(seq, 0, 0, &[0u32; 16], 0)).await?;
+    let mut buf = [0u8; HEADER_LEN];
+    stream.read_exact(&mut buf).await.map_err(handshake_error)?;
+    if !Packet::has_magic(&buf) {
+        return Err(Error::ProtocolMismatch);
+    }
+    let (packet, data_len) = Packet::from_header(&buf);
+    if packet.cmd != 0 || data_len > max_data_len as usize {
+        return Err(Error::ProtocolMismatch);
+    }
+
+    // the heartbeat's text output is skipped; nothing can be waiting for it yet
+    let skipped = tokio::io::copy(&mut (&mut *stream).take(data_len as u64), &mut tokio::io::sink())
+        .await
+        .map_err(handshake_error)?;
+    if skipped != data_len as u64 {
+        return Err(Error::Disconnected);
+    }
+    Ok(())
+}
+
+/// Writes the packets queued by a `Writer` to the socket, one whole packet at a time.
+async fn write(mut stream: OwnedWriteHalf,
+               mut rx: mpsc::UnboundedReceiver<Vec<u8>>,
+               connected: Arc<AtomicBool>) {
+    while let Some(packet) = rx.recv().await {
+        if stream.write_all(&packet).await.is_err() {
+            break;
+        }
+    }
+    connected.store(false, Ordering::SeqCst);
+}
+
+/// Reads packets from NTR and hands them to whoever is waiting on them.
+async fn receive(mut stream: OwnedReadHalf,
+                 connected: Arc<AtomicBool>,
+                 pending: PendingResponses,
+                 heartbeat_sendable: Arc<AtomicBool>,
+                 process_list_tx: mpsc::UnboundedSender<String>,
+                 max_data_len: usize) {
+    let mut buf = [0u8; HEADER_LEN];
+    let mut process_list_text = OutputBuffer::new(PROCESS_LIST_END);
+    loop {
+        if stream.read_exact(&mut buf).await.is_err() || !Packet::has_magic(&buf) {
+            break;
+        }
+        let (mut packet, data_len) = Packet::from_header(&buf);
+        if data_len > max_data_len {
+            break;
+        }
+        if packet.cmd == 0 {
+            heartbeat_sendable.store(true, Ordering::SeqCst);
+        }
+        if data_len != 0 {
+            packet.data = vec![0u8; data_len];
+            if stream.read_exact(&mut packet.data).await.is_err() {
+                break;
+            }
+        }
+
+        let waiter = pending.lock().unwrap().remove(&packet.seq);
+        if let Some(tx) = waiter {
+            let _ = tx.send(packet);
+        } else if packet.cmd == 0 && !packet.data.is_empty() {
+            if let Some(list) = process_list_text.push(&String::from_utf8_lossy(&packet.data)) {
+                if process_list_tx.send(list).is_err() {
+                    break;
+                }
+            }
+        }
+    }
+    connected.store(false, Ordering::SeqCst);
+    pending.lock().unwrap().clear();
+}
+
+/// Sends a heartbeat every `interval` once the previous one has been answered.
+///
+/// NTR only sends its text output, like the process list, in reply to a heartbeat.
+async fn heartbeat(writer: Arc<StdMutex<Writer>>,
+                   connected: Arc<AtomicBool>,
+                   heartbeat_sendable: Arc<AtomicBool>,
+                   interval: Duration) {
+    // `time::interval` doesn't accept a zero period
+    let mut ticker = time::interval(interval.max(Duration::from_millis(MIN_POLL_INTERVAL_MS)));
+    while connected.load(Ordering::SeqCst) {
+        ticker.tick().await;
+        if heartbeat_sendable.swap(false, Ordering::SeqCst) &&
+           writer
+               .lock()
+               .unwrap()
+               .send(0, 0, &[0u32; 16], &[])
+               .is_err() {
+            connected.store(false, Ordering::SeqCst);
+            break;
+        }
+    }
+}
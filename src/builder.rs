@@ -0,0 +1,210 @@
+use crate::addr::private::Target;
+use std::time::Duration;
+use crate::{CHUNK_LEN, Connection, Result, ToAddr};
+#[cfg(feature = "tokio")]
+use crate::AsyncConnection;
+use crate::{DEFAULT_CONNECT_TIMEOUT_SECS, DEFAULT_HEARTBEAT_INTERVAL_SECS, DEFAULT_INITIAL_SEQ,
+            DEFAULT_MAX_DATA_LEN, DEFAULT_READ_TIMEOUT_SECS, DEFAULT_SOCKET_READ_TIMEOUT_SECS,
+            DEFAULT_SOCKET_WRITE_TIMEOUT_SECS};
+
+/// Configures and opens a [`Connection`](struct.Connection.html).
+///
+/// Every option starts out at the default `Connection::new` uses.
+///
+/// # Examples
+///
+/// ```no_run
+/// use ntr::ConnectionBuilder;
+/// use std::time::Duration;
+///
+/// let connection = ConnectionBuilder::new("192.168.2.247")
+///     .port(8000)
+///     .heartbeat_interval(Some(Duration::from_secs(2)))
+///     .read_timeout(Duration::from_secs(3))
+///     .connect()
+///     .expect("couldn't connect");
+/// ```
+#[derive(Clone, Debug)]
+pub struct ConnectionBuilder {
+    pub(crate) target: Target,
+    pub(crate) port: Option<u16>,
+    pub(crate) connect_timeout: Option<Duration>,
+    pub(crate) heartbeat_interval: Option<Duration>,
+    pub(crate) read_timeout: Duration,
+    pub(crate) initial_seq: u32,
+    pub(crate) validate_addresses: bool,
+    pub(crate) handshake: bool,
+    pub(crate) max_data_len: u32,
+    pub(crate) idle_timeout: Option<Duration>,
+    pub(crate) chunk_len: u32,
+    pub(crate) socket_read_timeout: Option<Duration>,
+    pub(crate) socket_write_timeout: Option<Duration>,
+}
+
+impl ConnectionBuilder {
+    /// Starts configuring a connection to the 3DS with the address `addr`.
+    ///
+    /// See [`ToAddr`](trait.ToAddr.html) for the forms the address can take.
+    pub fn new<A: ToAddr>(addr: A) -> Self {
+        ConnectionBuilder {
+            target: addr.target(),
+            port: None,
+            connect_timeout: Some(Duration::from_secs(DEFAULT_CONNECT_TIMEOUT_SECS)),
+            heartbeat_interval: Some(Duration::from_secs(DEFAULT_HEARTBEAT_INTERVAL_SECS)),
+            read_timeout: Duration::from_secs(DEFAULT_READ_TIMEOUT_SECS),
+            initial_seq: DEFAULT_INITIAL_SEQ,
+            validate_addresses: true,
+            handshake: true,
+            max_data_len: DEFAULT_MAX_DATA_LEN,
+            idle_timeout: None,
+            chunk_len: CHUNK_LEN,
+            socket_read_timeout: Some(Duration::from_secs(DEFAULT_SOCKET_READ_TIMEOUT_SECS)),
+            socket_write_timeout: Some(Duration::from_secs(DEFAULT_SOCKET_WRITE_TIMEOUT_SECS)),
+        }
+    }
+
+    /// Sets the port the NTR debugger is reached on, overriding any port in the address. The
+    /// default is the address's port if it has one, or 8000 if it doesn't.
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = Some(port);
+        self
+    }
+
+    /// Sets how long opening the connection waits for the 3DS to answer, or waits as long as the
+    /// operating system allows with `None`. The default is 5 seconds.
+    ///
+    /// Without a timeout, a wrong address or a 3DS that's switched off can keep `connect`
+    /// blocked for over a minute. When the limit is reached, `connect` fails with an
+    /// `Error::Io` of kind `TimedOut`. A host name that resolves to several addresses gets the
+    /// full timeout for each one.
+    pub fn connect_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Sets how often heartbeats are sent, or disables them with `None`. The default is once a
+    /// second. With a zero interval, each heartbeat is sent about a millisecond after the last
+    /// one is answered.
+    ///
+    /// See `Connection::with_heartbeat_interval` for what stops working without heartbeats.
+    pub fn heartbeat_interval(mut self, interval: Option<Duration>) -> Self {
+        self.heartbeat_interval = interval;
+        self
+    }
+
+    /// Sets how long reads wait for a response before giving up. The default is 10 seconds.
+    pub fn read_timeout(mut self, timeout: Duration) -> Self {
+        self.read_timeout = timeout;
+        self
+    }
+
+    /// Sets the sequence number of the first packet sent. The default is 1000.
+    ///
+    /// Each packet's sequence number is 1000 more than the previous one's, wrapping around on
+    /// overflow. Matching another NTR client's numbering makes packet captures of the two easier
+    /// to compare.
+    pub fn initial_seq(mut self, seq: u32) -> Self {
+        self.initial_seq = seq;
+        self
+    }
+
+    /// Sets whether reads and writes check their address and size before sending anything. The
+    /// default is to check.
+    ///
+    /// NTR doesn't answer a read it can't serve, so a bad address otherwise shows up as a read
+    /// that blocks until it times out. With checking on, a read or write fails right away with
+    /// an `Error::Io` of kind `InvalidInput` if the address is zero, the size is zero, or the
+    /// range runs past `0xFFFFFFFF`. Other unmapped addresses still aren't caught.
+    pub fn validate_addresses(mut self, validate: bool) -> Self {
+        self.validate_addresses = validate;
+        self
+    }
+
+    /// Sets whether opening the connection checks that NTR is on the other end. The default is
+    /// to check.
+    ///
+    /// The check sends a heartbeat and waits up to the [`read_timeout`](#method.read_timeout)
+    /// for the reply. `connect` fails with `Error::ProtocolMismatch` if the reply isn't an NTR
+    /// packet, as happens when the address points at a web server, and with `Error::Timeout`
+    /// if nothing comes back. Without the check, a connection to the wrong server opens fine
+    /// and the first request hangs instead.
+    pub fn handshake(mut self, handshake: bool) -> Self {
+        self.handshake = handshake;
+        self
+    }
+
+    /// Sets the most data a packet from NTR may carry. The default is 16 MiB.
+    ///
+    /// The length of a packet's data comes straight from its header, so a corrupted header
+    /// could otherwise make the connection try to allocate up to 4 GiB for it. A packet
+    /// announcing more than `max` bytes is taken as a sign that the stream is corrupted, and the
+    /// connection is dropped as if it had been lost; a packet logger still sees its header.
+    /// Memory reads never get more than 64 KiB per packet, but a heartbeat reply carries all of
+    /// NTR's text output since the last one.
+    pub fn max_data_len(mut self, max: u32) -> Self {
+        self.max_data_len = max;
+        self
+    }
+
+    /// Sets how long the connection may go without any requests before it closes itself, or
+    /// keeps it open indefinitely with `None`. The default is `None`.
+    ///
+    /// NTR only serves one client at a time, so a tool that keeps its connection around
+    /// between bursts of activity can use this to let other clients in. Every request other
+    /// than a heartbeat counts as activity, and the connection isn't considered idle while a
+    /// request is waiting for its reply. Once closed, calls fail with `Error::Disconnected`
+    /// until `reconnect` is called.
+    pub fn idle_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// Sets the most memory read or written per packet. The default, and the most NTR
+    /// supports, is 64 KiB.
+    ///
+    /// Reads and writes larger than this are split into several packets. Over a flaky link,
+    /// smaller chunks can succeed where large ones keep failing, at some cost in throughput.
+    /// Sizes above 64 KiB are capped to 64 KiB, and a size of 0 is taken as 1.
+    pub fn chunk_size(mut self, size: u32) -> Self {
+        self.chunk_len = size.clamp(1, CHUNK_LEN);
+        self
+    }
+
+    /// Sets the read timeout of the underlying socket, or removes it with `None`. The default is
+    /// one second.
+    ///
+    /// This isn't how long reads wait for NTR to answer; see [`read_timeout`](#method.read_timeout)
+    /// for that. The background thread receiving NTR's packets just wakes up this often while
+    /// the socket is quiet to check whether the connection has been closed, then keeps waiting.
+    pub fn socket_read_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.socket_read_timeout = timeout;
+        self
+    }
+
+    /// Sets the write timeout of the underlying socket, or removes it with `None`. The default is
+    /// 10 seconds.
+    ///
+    /// A send that can't finish in time fails with an `Error::Io` of kind `WouldBlock` or
+    /// `TimedOut` instead of hanging on a wedged socket. The packet may have been partly sent,
+    /// so the connection should be reconnected afterwards.
+    pub fn socket_write_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.socket_write_timeout = timeout;
+        self
+    }
+
+    /// Opens the connection.
+    pub fn connect(&self) -> Result<Connection> {
+        Connection::connect(self)
+    }
+
+    /// Opens the connection as an [`AsyncConnection`](struct.AsyncConnection.html).
+    ///
+    /// This must be called from within a Tokio runtime, and is only available with the `tokio`
+    /// feature enabled. The options apply as they do to `connect`, except that the connect
+    /// timeout covers every address a host name resolves to together rather than each one, and
+    /// the socket timeouts and idle timeout are ignored.
+    #[cfg(feature = "tokio")]
+    pub async fn connect_async(&self) -> Result<AsyncConnection> {
+        AsyncConnection::connect(self).await
+    }
+}
@@ -0,0 +1,72 @@
+use std::ops::Deref;
+use std::sync::Arc;
+use crate::{Connection, Result, ToAddr};
+
+/// A cheaply cloneable handle to a shared [`Connection`](struct.Connection.html).
+///
+/// Every clone talks to the 3DS over the same connection, so one can be handed to each worker
+/// thread. `Client` dereferences to `Connection`, so all of its `&self` methods are available
+/// directly; responses are matched to requests by sequence number, so concurrent calls from
+/// different clones each get their own reply. The connection is closed once the last clone is
+/// dropped.
+///
+/// Methods that need `&mut Connection`, like `reconnect`, aren't available through a `Client`,
+/// since other clones may be using the connection at the same time.
+///
+/// # Examples
+///
+/// ```no_run
+/// use ntr::Client;
+/// use std::thread;
+///
+/// # let pid = ntr::Pid(0);
+/// let client = Client::new("192.168.2.247").expect("couldn't connect");
+/// let workers: Vec<_> = (0..4u32)
+///     .map(|i| {
+///         let client = client.clone();
+///         thread::spawn(move || client.read_u32(0x8000000 + i * 4, pid))
+///     })
+///     .collect();
+/// for worker in workers {
+///     println!("{:?}", worker.join().unwrap());
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct Client {
+    connection: Arc<Connection>,
+}
+
+impl Client {
+    /// Opens a connection to the 3DS with the address `addr` and wraps it in a `Client`.
+    ///
+    /// See [`Connection::new`](struct.Connection.html#method.new).
+    pub fn new<A: ToAddr>(addr: A) -> Result<Self> {
+        Connection::new(addr).map(Client::from)
+    }
+}
+
+impl From<Connection> for Client {
+    fn from(connection: Connection) -> Self {
+        Client { connection: Arc::new(connection) }
+    }
+}
+
+impl From<Arc<Connection>> for Client {
+    fn from(connection: Arc<Connection>) -> Self {
+        Client { connection }
+    }
+}
+
+impl Deref for Client {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        &self.connection
+    }
+}
+
+impl AsRef<Connection> for Client {
+    fn as_ref(&self) -> &Connection {
+        &self.connection
+    }
+}
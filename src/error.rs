@@ -0,0 +1,101 @@
+use std::error;
+use std::fmt;
+use std::io;
+use std::result;
+
+/// The error type for operations on a [`Connection`](struct.Connection.html).
+#[derive(Debug)]
+pub enum Error {
+    /// An IO error occurred while talking to the 3DS.
+    Io(io::Error),
+    /// NTR didn't respond in time.
+    Timeout,
+    /// The connection to the 3DS was lost.
+    ///
+    /// A connection is also dropped when a packet from NTR doesn't start with the magic number,
+    /// since the packets after it can't be told apart anymore.
+    Disconnected,
+    /// No running process matched the lookup.
+    PidNotFound,
+    /// A response from NTR couldn't be parsed.
+    Parse(String),
+    /// NTR answered a memory read with no data, even after the read was retried.
+    ///
+    /// This happens now and then for valid addresses, mostly right after attaching to a
+    /// process; trying again a little later usually works.
+    EmptyResponse,
+    /// The other end of the connection answered, but not with NTR packets, usually because the
+    /// address or port points at some other server.
+    ProtocolMismatch,
+    /// Memory read back after a write didn't match what was written.
+    VerifyFailed,
+    /// A pointer read from memory was null.
+    NullPointer {
+        /// The address the pointer was read from.
+        addr: u32,
+    },
+    /// Adding an offset to an address went past `0xFFFFFFFF`, usually because the address was a
+    /// garbage pointer.
+    InvalidAddress {
+        /// The address the offset was added to.
+        base: u32,
+        /// The offset.
+        offset: u32,
+    },
+}
+
+/// A specialized `Result` type for operations on a [`Connection`](struct.Connection.html).
+pub type Result<T> = result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref err) => write!(f, "io error: {}", err),
+            Error::Timeout => write!(f, "timed out waiting for a response from NTR"),
+            Error::Disconnected => write!(f, "the connection to the 3DS was lost"),
+            Error::PidNotFound => write!(f, "no matching process is running"),
+            Error::Parse(ref msg) => write!(f, "couldn't parse response from NTR: {}", msg),
+            Error::EmptyResponse => write!(f, "NTR answered a memory read with no data"),
+            Error::ProtocolMismatch => {
+                write!(f, "the remote end doesn't speak the NTR debugger protocol")
+            }
+            Error::VerifyFailed => write!(f, "memory didn't match the data written to it"),
+            Error::NullPointer { addr } => write!(f, "the pointer at {:#010x} is null", addr),
+            Error::InvalidAddress { base, offset } => {
+                write!(f, "address {:#010x} plus offset {:#x} overflows", base, offset)
+            }
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Io(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<Error> for io::Error {
+    /// Unwraps `Error::Io`, and wraps every other error in an `io::Error` of a fitting kind, so
+    /// `Connection` errors can pass through the `std::io` traits.
+    fn from(err: Error) -> Self {
+        let kind = match err {
+            Error::Io(err) => return err,
+            Error::Timeout => io::ErrorKind::TimedOut,
+            Error::Disconnected => io::ErrorKind::NotConnected,
+            Error::ProtocolMismatch => io::ErrorKind::InvalidData,
+            Error::NullPointer { .. } |
+            Error::InvalidAddress { .. } => io::ErrorKind::InvalidInput,
+            _ => io::ErrorKind::Other,
+        };
+        io::Error::new(kind, err)
+    }
+}
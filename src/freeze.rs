@@ -0,0 +1,190 @@
+use crate::{MIN_POLL_INTERVAL_MS, Pid, Result, ToMemBytes};
+use crate::link::Link;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// A value being kept frozen in 3DS memory by a background thread.
+///
+/// Returned by [`Connection::freeze`](struct.Connection.html#method.freeze). The freeze ends
+/// when the handle is dropped or [`stop`](#method.stop) is called, or when the connection is
+/// lost.
+#[derive(Debug)]
+pub struct FreezeHandle {
+    stop_tx: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl FreezeHandle {
+    pub(crate) fn spawn(link: Link,
+                        addr: u32,
+                        data: Vec<u8>,
+                        pid: Pid,
+                        interval: Duration)
+                        -> Self {
+        // a zero interval would write in a tight loop, starving everything else on the socket
+        let interval = interval.max(Duration::from_millis(MIN_POLL_INTERVAL_MS));
+        let (stop_tx, stop_rx) = mpsc::channel::<()>();
+        let thread = thread::spawn(move || {
+            while link.connected.load(Ordering::SeqCst) {
+                let mut ntr_sender = link.ntr_sender.lock().unwrap();
+                if link.send_mem_write(&mut ntr_sender, addr, &data, pid).is_err() {
+                    break;
+                }
+                drop(ntr_sender);
+
+                // doubles as the sleep between writes; ends early once the handle goes away
+                if let Err(RecvTimeoutError::Disconnected) = stop_rx.recv_timeout(interval) {
+                    break;
+                }
+            }
+        });
+
+        FreezeHandle {
+            stop_tx: Some(stop_tx),
+            thread: Some(thread),
+        }
+    }
+
+    /// Stops writing the value and waits for the background thread to exit.
+    pub fn stop(self) {
+        // dropping the handle does the work
+    }
+}
+
+impl Drop for FreezeHandle {
+    fn drop(&mut self) {
+        self.stop_tx.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// The entries of a `FreezeSet`: the bytes to write, keyed by address and process id.
+type FreezeEntries = Arc<Mutex<BTreeMap<(u32, Pid), Vec<u8>>>>;
+
+/// Many values being kept frozen in 3DS memory by a single background thread.
+///
+/// Returned by [`Connection::freeze_set`](struct.Connection.html#method.freeze_set). Every
+/// interval, the thread writes each value in the set one after another, in order of address,
+/// without letting other requests in between. Compared to a `FreezeHandle` per value, this
+/// uses one thread no matter how many values are frozen, and keeps their writes together on
+/// the wire.
+///
+/// Values can be added and removed at any time, including from other threads. The set stops
+/// writing when it's dropped or [`stop`](#method.stop) is called, or when the connection is
+/// lost.
+///
+/// # Examples
+///
+/// ```no_run
+/// use ntr::{Connection, Value};
+/// use std::time::Duration;
+///
+/// # let connection: Connection = unimplemented!();
+/// # let pid = ntr::Pid(0);
+/// let freezes = connection.freeze_set(Duration::from_millis(100));
+/// freezes.add(0x8001000, 9999u32, pid).expect("invalid address");
+/// freezes.add(0x8001004, Value::F32(100.0), pid).expect("invalid address");
+/// // ...
+/// freezes.remove(0x8001000, pid);
+/// ```
+#[derive(Debug)]
+pub struct FreezeSet {
+    link: Link,
+    entries: FreezeEntries,
+    stop_tx: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl FreezeSet {
+    pub(crate) fn spawn(link: Link, interval: Duration) -> Self {
+        // a zero interval would write in a tight loop, starving everything else on the socket
+        let interval = interval.max(Duration::from_millis(MIN_POLL_INTERVAL_MS));
+        let entries = FreezeEntries::default();
+        let (stop_tx, stop_rx) = mpsc::channel::<()>();
+        let thread = {
+            let entries = entries.clone();
+            let link = link.clone();
+            thread::spawn(move || {
+                while link.connected.load(Ordering::SeqCst) {
+                    {
+                        let entries = entries.lock().unwrap();
+                        let mut ntr_sender = link.ntr_sender.lock().unwrap();
+                        for (&(addr, pid), data) in entries.iter() {
+                            if link.send_mem_write(&mut ntr_sender, addr, data, pid).is_err() {
+                                return;
+                            }
+                        }
+                    }
+
+                    // doubles as the sleep between ticks; ends early once the set goes away
+                    if let Err(RecvTimeoutError::Disconnected) = stop_rx.recv_timeout(interval) {
+                        break;
+                    }
+                }
+            })
+        };
+
+        FreezeSet {
+            link,
+            entries,
+            stop_tx: Some(stop_tx),
+            thread: Some(thread),
+        }
+    }
+
+    /// Freezes the memory at `addr` in the process `pid` to `value`, starting with the next
+    /// tick.
+    ///
+    /// If a value is already frozen at `addr` in that process, it's replaced. `Value` can be
+    /// passed here to pick the type at runtime. The address is checked like
+    /// [`Connection::mem_write`](struct.Connection.html#method.mem_write) checks it, and an
+    /// address that fails the check isn't added.
+    pub fn add<T: ToMemBytes>(&self, addr: u32, value: T, pid: Pid) -> Result<()> {
+        let data = value.to_le_bytes();
+        self.link.validate_write(addr, &data)?;
+        self.entries.lock().unwrap().insert((addr, pid), data);
+        Ok(())
+    }
+
+    /// Stops freezing the value at `addr` in the process `pid`, returning whether one was
+    /// frozen there.
+    pub fn remove(&self, addr: u32, pid: Pid) -> bool {
+        self.entries.lock().unwrap().remove(&(addr, pid)).is_some()
+    }
+
+    /// Stops freezing every value, leaving the background thread running for values added
+    /// later.
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+
+    /// Returns the number of values being frozen.
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    /// Returns whether no values are being frozen.
+    pub fn is_empty(&self) -> bool {
+        self.entries.lock().unwrap().is_empty()
+    }
+
+    /// Stops writing every value and waits for the background thread to exit.
+    pub fn stop(self) {
+        // dropping the set does the work
+    }
+}
+
+impl Drop for FreezeSet {
+    fn drop(&mut self) {
+        self.stop_tx.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
@@ -0,0 +1,390 @@
+use crate::{Connection, FreezeHandle, FromMemBytes, MemReader, MemRegion, MemWriter, Pid, Result,
+            Sampler, StructLayout, ToMemBytes, Value, ValueType, WatchHandle};
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// A [`Connection`](struct.Connection.html) bound to a single process.
+///
+/// Returned by [`Connection::attach`](struct.Connection.html#method.attach). It offers the same
+/// reads and writes as the connection, without passing the process id to each call.
+///
+/// # Examples
+///
+/// ```no_run
+/// use ntr::Connection;
+///
+/// # let connection: Connection = unimplemented!();
+/// # let pid = ntr::Pid(0);
+/// let game = connection.attach(pid);
+/// let hp = game.read_u32(0x8001000).expect("io error");
+/// game.write_u32(0x8001000, hp + 100).expect("io error");
+/// ```
+#[derive(Clone, Copy, Debug)]
+pub struct ProcessHandle<'a> {
+    connection: &'a Connection,
+    pid: Pid,
+}
+
+impl<'a> ProcessHandle<'a> {
+    pub(crate) fn new(connection: &'a Connection, pid: Pid) -> Self {
+        ProcessHandle { connection, pid }
+    }
+
+    /// Returns the process id this handle is bound to.
+    pub fn pid(&self) -> Pid {
+        self.pid
+    }
+
+    /// Returns the connection this handle reads and writes through.
+    pub fn connection(&self) -> &'a Connection {
+        self.connection
+    }
+
+    /// Returns the mapped regions of the process's memory. See
+    /// [`Connection::memory_regions`](struct.Connection.html#method.memory_regions).
+    pub fn memory_regions(&self) -> Result<Vec<MemRegion>> {
+        self.connection.memory_regions(self.pid)
+    }
+
+    /// Reads a chunk of the process's memory. See
+    /// [`Connection::mem_read`](struct.Connection.html#method.mem_read).
+    pub fn mem_read(&self, addr: u32, size: u32) -> Result<Box<[u8]>> {
+        self.connection.mem_read(addr, size, self.pid)
+    }
+
+    /// Reads a chunk of the process's memory using only aligned requests. See
+    /// [`Connection::mem_read_aligned`](struct.Connection.html#method.mem_read_aligned).
+    pub fn mem_read_aligned(&self, addr: u32, size: u32) -> Result<Box<[u8]>> {
+        self.connection.mem_read_aligned(addr, size, self.pid)
+    }
+
+    /// Reads a chunk of the process's memory into a `Vec`. See
+    /// [`Connection::read_vec`](struct.Connection.html#method.read_vec).
+    pub fn read_vec(&self, addr: u32, size: u32) -> Result<Vec<u8>> {
+        self.connection.read_vec(addr, size, self.pid)
+    }
+
+    /// Reads the process's memory into a caller-provided buffer. See
+    /// [`Connection::read_into`](struct.Connection.html#method.read_into).
+    pub fn read_into(&self, addr: u32, buf: &mut [u8]) -> Result<()> {
+        self.connection.read_into(addr, buf, self.pid)
+    }
+
+    /// Writes data to the process's memory. See
+    /// [`Connection::mem_write`](struct.Connection.html#method.mem_write).
+    pub fn mem_write(&self, addr: u32, data: &[u8]) -> Result<usize> {
+        self.connection.mem_write(addr, data, self.pid)
+    }
+
+    /// Returns a reader over the process's memory. See
+    /// [`Connection::mem_reader`](struct.Connection.html#method.mem_reader).
+    pub fn mem_reader(&self, addr: u32) -> MemReader<'a> {
+        self.connection.mem_reader(addr, self.pid)
+    }
+
+    /// Returns a writer into the process's memory. See
+    /// [`Connection::mem_writer`](struct.Connection.html#method.mem_writer).
+    pub fn mem_writer(&self, addr: u32) -> MemWriter<'a> {
+        self.connection.mem_writer(addr, self.pid)
+    }
+
+    /// Reads a value of any type implementing `FromMemBytes` from the process's memory.
+    pub fn read<T: FromMemBytes>(&self, addr: u32) -> Result<T> {
+        self.connection.read(addr, self.pid)
+    }
+
+    /// Reads a big-endian value of any type implementing `FromMemBytes` from the process's
+    /// memory.
+    pub fn read_be<T: FromMemBytes>(&self, addr: u32) -> Result<T> {
+        self.connection.read_be(addr, self.pid)
+    }
+
+    /// Reads a value whose type is chosen at runtime from the process's memory. See
+    /// [`Connection::read_value`](struct.Connection.html#method.read_value).
+    pub fn read_value(&self, addr: u32, ty: ValueType) -> Result<Value> {
+        self.connection.read_value(addr, ty, self.pid)
+    }
+
+    /// Reads the fields of a structure in the process's memory. See
+    /// [`Connection::read_struct`](struct.Connection.html#method.read_struct).
+    pub fn read_struct(&self, base: u32, layout: &StructLayout) -> Result<HashMap<String, Value>> {
+        self.connection.read_struct(base, layout, self.pid)
+    }
+
+    /// Reads `count` consecutive values from the process's memory. See
+    /// [`Connection::read_slice`](struct.Connection.html#method.read_slice).
+    pub fn read_slice<T: FromMemBytes>(&self, addr: u32, count: u32) -> Result<Vec<T>> {
+        self.connection.read_slice(addr, count, self.pid)
+    }
+
+    /// Reads `count` consecutive `u32`s from the process's memory.
+    pub fn read_u32_slice(&self, addr: u32, count: u32) -> Result<Vec<u32>> {
+        self.connection.read_u32_slice(addr, count, self.pid)
+    }
+
+    /// Writes a value of any type implementing `ToMemBytes` to the process's memory.
+    pub fn write<T: ToMemBytes>(&self, addr: u32, data: T) -> Result<()> {
+        self.connection.write(addr, data, self.pid)
+    }
+
+    /// Writes a value of any type implementing `ToMemBytes` to the process's memory in
+    /// big-endian byte order.
+    pub fn write_be<T: ToMemBytes>(&self, addr: u32, data: T) -> Result<()> {
+        self.connection.write_be(addr, data, self.pid)
+    }
+
+    /// Writes a value whose type is chosen at runtime to the process's memory. See
+    /// [`Connection::write_value`](struct.Connection.html#method.write_value).
+    pub fn write_value(&self, addr: u32, value: Value) -> Result<()> {
+        self.connection.write_value(addr, value, self.pid)
+    }
+
+    /// Writes consecutive values to the process's memory. See
+    /// [`Connection::write_slice`](struct.Connection.html#method.write_slice).
+    pub fn write_slice<T: ToMemBytes>(&self, addr: u32, data: &[T]) -> Result<()> {
+        self.connection.write_slice(addr, data, self.pid)
+    }
+
+    /// Writes consecutive `u32`s to the process's memory.
+    pub fn write_u32_slice(&self, addr: u32, data: &[u32]) -> Result<()> {
+        self.connection.write_u32_slice(addr, data, self.pid)
+    }
+
+    /// Reads a `u64` from the process's memory.
+    pub fn read_u64(&self, addr: u32) -> Result<u64> {
+        self.connection.read_u64(addr, self.pid)
+    }
+
+    /// Reads a `u32` from the process's memory.
+    pub fn read_u32(&self, addr: u32) -> Result<u32> {
+        self.connection.read_u32(addr, self.pid)
+    }
+
+    /// Reads a 3-byte unsigned integer from the process's memory.
+    pub fn read_u24(&self, addr: u32) -> Result<u32> {
+        self.connection.read_u24(addr, self.pid)
+    }
+
+    /// Reads a `u16` from the process's memory.
+    pub fn read_u16(&self, addr: u32) -> Result<u16> {
+        self.connection.read_u16(addr, self.pid)
+    }
+
+    /// Reads a `u8` from the process's memory.
+    pub fn read_u8(&self, addr: u32) -> Result<u8> {
+        self.connection.read_u8(addr, self.pid)
+    }
+
+    /// Reads an `i64` from the process's memory.
+    pub fn read_i64(&self, addr: u32) -> Result<i64> {
+        self.connection.read_i64(addr, self.pid)
+    }
+
+    /// Reads an `i32` from the process's memory.
+    pub fn read_i32(&self, addr: u32) -> Result<i32> {
+        self.connection.read_i32(addr, self.pid)
+    }
+
+    /// Reads an `i16` from the process's memory.
+    pub fn read_i16(&self, addr: u32) -> Result<i16> {
+        self.connection.read_i16(addr, self.pid)
+    }
+
+    /// Reads an `i8` from the process's memory.
+    pub fn read_i8(&self, addr: u32) -> Result<i8> {
+        self.connection.read_i8(addr, self.pid)
+    }
+
+    /// Reads an `f32` from the process's memory.
+    pub fn read_f32(&self, addr: u32) -> Result<f32> {
+        self.connection.read_f32(addr, self.pid)
+    }
+
+    /// Reads an `f64` from the process's memory.
+    pub fn read_f64(&self, addr: u32) -> Result<f64> {
+        self.connection.read_f64(addr, self.pid)
+    }
+
+    /// Reads a one-byte flag from the process's memory.
+    pub fn read_bool(&self, addr: u32) -> Result<bool> {
+        self.connection.read_bool(addr, self.pid)
+    }
+
+    /// Reads a fixed-point number from the process's memory. See
+    /// [`Connection::read_fixed`](struct.Connection.html#method.read_fixed).
+    pub fn read_fixed(&self, addr: u32, frac_bits: u32) -> Result<f64> {
+        self.connection.read_fixed(addr, frac_bits, self.pid)
+    }
+
+    /// Reads a null-terminated UTF-8 string from the process's memory. See
+    /// [`Connection::read_cstring`](struct.Connection.html#method.read_cstring).
+    pub fn read_cstring(&self, addr: u32, max_len: u32) -> Result<String> {
+        self.connection.read_cstring(addr, max_len, self.pid)
+    }
+
+    /// Reads a null-terminated UTF-16LE string from the process's memory. See
+    /// [`Connection::read_utf16_string`](struct.Connection.html#method.read_utf16_string).
+    pub fn read_utf16_string(&self, addr: u32, max_units: u32) -> Result<String> {
+        self.connection.read_utf16_string(addr, max_units, self.pid)
+    }
+
+    /// Writes a `u64` to the process's memory.
+    pub fn write_u64(&self, addr: u32, data: u64) -> Result<()> {
+        self.connection.write_u64(addr, data, self.pid)
+    }
+
+    /// Writes a `u32` to the process's memory.
+    pub fn write_u32(&self, addr: u32, data: u32) -> Result<()> {
+        self.connection.write_u32(addr, data, self.pid)
+    }
+
+    /// Writes a `u32` to the process's memory unless it's already there. See
+    /// [`Connection::write_u32_if_changed`](struct.Connection.html#method.write_u32_if_changed).
+    pub fn write_u32_if_changed(&self, addr: u32, data: u32) -> Result<bool> {
+        self.connection.write_u32_if_changed(addr, data, self.pid)
+    }
+
+    /// Writes the low 3 bytes of `data` to the process's memory. See
+    /// [`Connection::write_u24`](struct.Connection.html#method.write_u24).
+    pub fn write_u24(&self, addr: u32, data: u32) -> Result<()> {
+        self.connection.write_u24(addr, data, self.pid)
+    }
+
+    /// Writes a `u16` to the process's memory.
+    pub fn write_u16(&self, addr: u32, data: u16) -> Result<()> {
+        self.connection.write_u16(addr, data, self.pid)
+    }
+
+    /// Writes a `u8` to the process's memory.
+    pub fn write_u8(&self, addr: u32, data: u8) -> Result<()> {
+        self.connection.write_u8(addr, data, self.pid)
+    }
+
+    /// Writes an `i64` to the process's memory.
+    pub fn write_i64(&self, addr: u32, data: i64) -> Result<()> {
+        self.connection.write_i64(addr, data, self.pid)
+    }
+
+    /// Writes an `i32` to the process's memory.
+    pub fn write_i32(&self, addr: u32, data: i32) -> Result<()> {
+        self.connection.write_i32(addr, data, self.pid)
+    }
+
+    /// Writes an `i16` to the process's memory.
+    pub fn write_i16(&self, addr: u32, data: i16) -> Result<()> {
+        self.connection.write_i16(addr, data, self.pid)
+    }
+
+    /// Writes an `i8` to the process's memory.
+    pub fn write_i8(&self, addr: u32, data: i8) -> Result<()> {
+        self.connection.write_i8(addr, data, self.pid)
+    }
+
+    /// Writes an `f32` to the process's memory.
+    pub fn write_f32(&self, addr: u32, data: f32) -> Result<()> {
+        self.connection.write_f32(addr, data, self.pid)
+    }
+
+    /// Writes an `f64` to the process's memory.
+    pub fn write_f64(&self, addr: u32, data: f64) -> Result<()> {
+        self.connection.write_f64(addr, data, self.pid)
+    }
+
+    /// Writes a one-byte flag to the process's memory.
+    pub fn write_bool(&self, addr: u32, data: bool) -> Result<()> {
+        self.connection.write_bool(addr, data, self.pid)
+    }
+
+    /// Writes a fixed-point number to the process's memory. See
+    /// [`Connection::write_fixed`](struct.Connection.html#method.write_fixed).
+    pub fn write_fixed(&self, addr: u32, value: f64, frac_bits: u32) -> Result<()> {
+        self.connection.write_fixed(addr, value, frac_bits, self.pid)
+    }
+
+    /// Follows a chain of pointers in the process's memory. See
+    /// [`Connection::follow_pointer_chain`](struct.Connection.html#method.follow_pointer_chain).
+    pub fn follow_pointer_chain(&self, base: u32, offsets: &[u32]) -> Result<u32> {
+        self.connection.follow_pointer_chain(base, offsets, self.pid)
+    }
+
+    /// Follows a chain of pointers and reads the `u32` at the end. See
+    /// [`Connection::read_u32_via`](struct.Connection.html#method.read_u32_via).
+    pub fn read_u32_via(&self, base: u32, offsets: &[u32]) -> Result<u32> {
+        self.connection.read_u32_via(base, offsets, self.pid)
+    }
+
+    /// Follows a chain of pointers and writes a `u32` at the end. See
+    /// [`Connection::write_u32_via`](struct.Connection.html#method.write_u32_via).
+    pub fn write_u32_via(&self, base: u32, offsets: &[u32], data: u32) -> Result<()> {
+        self.connection.write_u32_via(base, offsets, data, self.pid)
+    }
+
+    /// Reads a pointer from the process's memory. See
+    /// [`Connection::read_pointer`](struct.Connection.html#method.read_pointer).
+    pub fn read_pointer(&self, addr: u32) -> Result<u32> {
+        self.connection.read_pointer(addr, self.pid)
+    }
+
+    /// Reads a chunk of the process's memory at an offset from a base address. See
+    /// [`Connection::mem_read_off`](struct.Connection.html#method.mem_read_off).
+    pub fn mem_read_off(&self, base: u32, offset: u32, size: u32) -> Result<Box<[u8]>> {
+        self.connection.mem_read_off(base, offset, size, self.pid)
+    }
+
+    /// Writes data to the process's memory at an offset from a base address. See
+    /// [`Connection::mem_write_off`](struct.Connection.html#method.mem_write_off).
+    pub fn mem_write_off(&self, base: u32, offset: u32, data: &[u8]) -> Result<usize> {
+        self.connection.mem_write_off(base, offset, data, self.pid)
+    }
+
+    /// Reads a value at an offset from a base address. See
+    /// [`Connection::read_off`](struct.Connection.html#method.read_off).
+    pub fn read_off<T: FromMemBytes>(&self, base: u32, offset: u32) -> Result<T> {
+        self.connection.read_off(base, offset, self.pid)
+    }
+
+    /// Writes a value at an offset from a base address. See
+    /// [`Connection::write_off`](struct.Connection.html#method.write_off).
+    pub fn write_off<T: ToMemBytes>(&self, base: u32, offset: u32, data: T) -> Result<()> {
+        self.connection.write_off(base, offset, data, self.pid)
+    }
+
+    /// Reads a `u32` at an offset from a base address. See
+    /// [`Connection::read_u32_off`](struct.Connection.html#method.read_u32_off).
+    pub fn read_u32_off(&self, base: u32, offset: u32) -> Result<u32> {
+        self.connection.read_u32_off(base, offset, self.pid)
+    }
+
+    /// Writes a `u32` at an offset from a base address. See
+    /// [`Connection::write_u32_off`](struct.Connection.html#method.write_u32_off).
+    pub fn write_u32_off(&self, base: u32, offset: u32, data: u32) -> Result<()> {
+        self.connection.write_u32_off(base, offset, data, self.pid)
+    }
+
+    /// Keeps `value` frozen at `addr` in the process's memory. See
+    /// [`Connection::freeze`](struct.Connection.html#method.freeze).
+    pub fn freeze<T: ToMemBytes>(&self,
+                                 addr: u32,
+                                 value: T,
+                                 interval: Duration)
+                                 -> Result<FreezeHandle> {
+        self.connection.freeze(addr, value, self.pid, interval)
+    }
+
+    /// Calls `callback` whenever the value at `addr` in the process's memory changes. See
+    /// [`Connection::watch`](struct.Connection.html#method.watch).
+    pub fn watch<T, F>(&self, addr: u32, interval: Duration, callback: F) -> WatchHandle
+        where T: FromMemBytes + PartialEq + Clone + Send + 'static,
+              F: FnMut(T) + Send + 'static
+    {
+        self.connection.watch(addr, self.pid, interval, callback)
+    }
+
+    /// Reads the value at `addr` in the process's memory on a fixed schedule. See
+    /// [`Connection::sample`](struct.Connection.html#method.sample).
+    pub fn sample<T>(&self, addr: u32, interval: Duration) -> Sampler<T>
+        where T: FromMemBytes + Send + 'static
+    {
+        self.connection.sample(addr, self.pid, interval)
+    }
+}
@@ -4,226 +4,2278 @@
 #![warn(missing_copy_implementations, missing_debug_implementations, missing_docs,
     unused_extern_crates, unused_import_braces, unused_qualifications)]
 
-extern crate byteorder;
-extern crate regex;
-extern crate time;
-
+mod addr;
+#[cfg(feature = "tokio")]
+mod async_connection;
+mod builder;
+mod client;
+mod error;
+mod freeze;
+mod handle;
+mod link;
+mod mem_bytes;
+mod mem_region;
 mod ntr_sender;
+mod packet;
+mod process;
+pub mod regions;
+mod sample;
+mod scan;
+mod stats;
+mod stream;
+mod value;
+mod watch;
+
+pub use crate::addr::ToAddr;
+#[cfg(feature = "tokio")]
+pub use crate::async_connection::AsyncConnection;
+pub use crate::builder::ConnectionBuilder;
+pub use crate::client::Client;
+pub use crate::error::{Error, Result};
+pub use crate::freeze::{FreezeHandle, FreezeSet};
+pub use crate::handle::ProcessHandle;
+pub use crate::link::ReadToken;
+pub use crate::mem_bytes::{FromMemBytes, ToMemBytes};
+pub use crate::mem_region::{MemRegion, parse_memory_layout};
+pub use crate::packet::{Direction, Packet, PacketEvent};
+pub use crate::process::{Pid, ProcessInfo, parse_process_list};
+pub use crate::sample::{Sample, Sampler};
+pub use crate::scan::{Scanner, Snapshot, diff_regions};
+pub use crate::stats::ConnectionStats;
+pub use crate::stream::{MemReader, MemWriter};
+pub use crate::value::{StructLayout, Value, ValueType};
+pub use crate::watch::WatchHandle;
 
 use byteorder::{ByteOrder, LittleEndian};
 
-use ntr_sender::NtrSender;
+use crate::link::{Link, PendingResponses};
+use crate::ntr_sender::NtrSender;
+use crate::packet::{Callback, HEADER_LEN, PacketLogger};
+use crate::process::{MEMORY_LAYOUT_END, OutputBuffer, PROCESS_LIST_END};
+use crate::stats::StatsCounters;
 use regex::Regex;
+use std::cmp;
+use std::collections::HashMap;
 use std::io;
 use std::io::prelude::*;
-use std::net::TcpStream;
-use std::sync::{Arc, Mutex};
-use std::sync::mpsc::{self, Receiver};
-use std::thread;
-use std::time::Duration;
+use std::net::{Shutdown, TcpStream};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 use time::PreciseTime;
 
+/// The port the NTR debugger listens on.
+const DEFAULT_PORT: u16 = 8000;
+
+/// How often heartbeats are sent by default.
+const DEFAULT_HEARTBEAT_INTERVAL_SECS: u64 = 1;
+
+/// How long opening a connection waits for the 3DS to answer by default.
+const DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 5;
+
+/// The read timeout of the socket by default.
+const DEFAULT_SOCKET_READ_TIMEOUT_SECS: u64 = 1;
+
+/// The write timeout of the socket by default.
+const DEFAULT_SOCKET_WRITE_TIMEOUT_SECS: u64 = 10;
+
+/// The sequence number of the first packet sent by default.
+const DEFAULT_INITIAL_SEQ: u32 = 1000;
+
+/// How long `mem_read` waits for a response by default.
+const DEFAULT_READ_TIMEOUT_SECS: u64 = 10;
+
+/// The most data a received packet may carry by default.
+const DEFAULT_MAX_DATA_LEN: u32 = 0x1000000;
+
+/// The most data transferred in a single packet; larger reads and writes are split into chunks
+/// of this size.
+const CHUNK_LEN: u32 = 0x10000;
+
+/// How much of the caller's buffer `read_into` requests at a time.
+const READ_INTO_WINDOW_LEN: usize = 16 * CHUNK_LEN as usize;
+
+/// The longest `reconnect_with_backoff` sleeps between attempts.
+const MAX_RECONNECT_DELAY_SECS: u64 = 30;
+
+/// The alignment `mem_read_aligned` rounds reads out to.
+const READ_ALIGN: u64 = 4;
+
+/// How long `reconnect_preserving_pids` waits for each process to answer a read.
+const PID_CHECK_TIMEOUT_MS: u64 = 1000;
+
+/// How often `wait_for_process` checks the process list.
+const WAIT_FOR_PROCESS_POLL_MS: u64 = 500;
+
+/// How often `freeze_u32` rewrites its value.
+const DEFAULT_FREEZE_INTERVAL_MS: u64 = 100;
+
+/// The longest the heartbeat and idle timeout threads sleep between checks.
+const MAX_POLL_INTERVAL_MS: u64 = 500;
+
+/// The shortest the heartbeat and idle timeout threads sleep between checks, so a zero interval
+/// doesn't make them spin on the sender lock.
+const MIN_POLL_INTERVAL_MS: u64 = 1;
+
 /// A connection to a 3DS.
+///
+/// All of the methods that talk to the 3DS take `&self`, and `Connection` is `Send` and `Sync`,
+/// so one connection can be shared between threads by wrapping it in an `Arc`. Responses are
+/// matched to their requests by sequence number, so concurrent reads from different threads
+/// each get their own data back. Requests from different threads are interleaved on the wire
+/// in whatever order the threads get to them; process list requests are serialized with each
+/// other. [`Client`](struct.Client.html) wraps the `Arc` in a cloneable handle.
+///
+/// # Examples
+///
+/// ```no_run
+/// use ntr::Connection;
+/// use std::sync::Arc;
+/// use std::thread;
+///
+/// # let pid = ntr::Pid(0);
+/// let connection = Arc::new(Connection::new("192.168.2.247").expect("couldn't connect"));
+/// let worker = {
+///     let connection = connection.clone();
+///     thread::spawn(move || connection.read_u32(0x8000000, pid))
+/// };
+/// let coords = connection.read_f32(0x8001000, pid);
+/// let hp = worker.join().unwrap();
+/// ```
 #[derive(Debug)]
 pub struct Connection {
-    ntr_sender: Arc<Mutex<NtrSender>>,
-    mem_read_rx: Receiver<Box<[u8]>>,
-    get_pid_rx: Receiver<String>,
+    link: Link,
+    process_list_rx: Mutex<Receiver<String>>,
+    memory_layout_rx: Mutex<Receiver<String>>,
+    hello_rx: Mutex<Receiver<String>>,
+    hello_reply: OnceLock<String>,
+    read_timeout: Duration,
+    heartbeat_interval: Option<Duration>,
+    heartbeat_paused: Arc<AtomicBool>,
+    last_heartbeat_ack: Arc<Mutex<Instant>>,
+    last_heartbeat_rtt: Arc<Mutex<Option<Duration>>>,
+    heartbeat_error: Arc<Mutex<Option<io::Error>>>,
+    tcp_stream: TcpStream,
+    threads: Vec<JoinHandle<()>>,
+    logger: PacketLogger,
+    message_handler: Callback<Packet>,
+    stats: Arc<StatsCounters>,
+    options: ConnectionBuilder,
 }
 
 impl Connection {
     /// Opens a connection to the 3DS with the address `addr`.
     ///
+    /// The address can be a host name or IP address, with or without a port, or a socket
+    /// address; see [`ToAddr`](trait.ToAddr.html). Without a port, the NTR debugger port (8000)
+    /// is used.
+    ///
+    /// Once connected, a heartbeat is exchanged to make sure NTR is on the other end; if
+    /// something else answers, this fails with `Error::ProtocolMismatch`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use ntr::Connection;
+    ///
+    /// let connection = Connection::new("192.168.2.247").expect("couldn't connect");
+    /// ```
+    pub fn new<A: ToAddr>(addr: A) -> Result<Self> {
+        ConnectionBuilder::new(addr).connect()
+    }
+
+    /// Opens a connection to the 3DS with the address `addr`, using `port` instead of the default
+    /// NTR debugger port (8000) or any port in `addr`.
+    ///
+    /// This is useful when the debugger is reached through a port forward or tunnel.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use ntr::Connection;
+    ///
+    /// let connection = Connection::with_port("localhost", 18000).expect("couldn't connect");
+    /// ```
+    pub fn with_port<A: ToAddr>(addr: A, port: u16) -> Result<Self> {
+        ConnectionBuilder::new(addr).port(port).connect()
+    }
+
+    /// Opens a connection to the 3DS with the address `addr`, sending heartbeats every
+    /// `heartbeat_interval` instead of every second.
+    ///
+    /// Passing `None` disables heartbeats and doesn't start the heartbeat thread at all. NTR
+    /// only sends its text output in reply to heartbeats, so without them
+    /// [`list_processes`](#method.list_processes), [`get_pid`](#method.get_pid) and
+    /// [`hello`](#method.hello) never get an answer; memory reads and writes are unaffected.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use ntr::Connection;
+    /// use std::time::Duration;
+    ///
+    /// let connection = Connection::with_heartbeat_interval("192.168.2.247",
+    ///                                                      Some(Duration::from_secs(5)))
+    ///     .expect("couldn't connect");
+    /// ```
+    pub fn with_heartbeat_interval<A: ToAddr>(addr: A,
+                                              heartbeat_interval: Option<Duration>)
+                                   -> Result<Self> {
+        ConnectionBuilder::new(addr)
+            .heartbeat_interval(heartbeat_interval)
+            .connect()
+    }
+
+    /// Opens a connection to the 3DS with the address `addr`, giving up after `timeout` instead
+    /// of the default 5 seconds if the 3DS doesn't answer.
+    ///
+    /// Fails with an `Error::Io` of kind `TimedOut` when the time runs out.
+    ///
     /// # Examples
     ///
     /// ```no_run
     /// use ntr::Connection;
+    /// use std::time::Duration;
     ///
-    /// let mut connection = Connection::new("192.168.2.247").expect("io error");
+    /// let connection = Connection::with_connect_timeout("192.168.2.247", Duration::from_secs(2))
+    ///     .expect("couldn't connect");
     /// ```
-    pub fn new(addr: &str) -> io::Result<Self> {
-        let mut tcp_stream = TcpStream::connect(&(addr.to_owned() + ":8000") as &str)?;
-        let (mem_read_tx, mem_read_rx) = mpsc::channel();
-        let (get_pid_tx, get_pid_rx) = mpsc::channel();
+    pub fn with_connect_timeout<A: ToAddr>(addr: A, timeout: Duration) -> Result<Self> {
+        ConnectionBuilder::new(addr)
+            .connect_timeout(Some(timeout))
+            .connect()
+    }
+
+    /// Starts configuring a connection to the 3DS with the address `addr`.
+    ///
+    /// This is the same as [`ConnectionBuilder::new`](struct.ConnectionBuilder.html#method.new).
+    pub fn builder<A: ToAddr>(addr: A) -> ConnectionBuilder {
+        ConnectionBuilder::new(addr)
+    }
+
+    fn connect(options: &ConnectionBuilder) -> Result<Self> {
+        Connection::open(options, PacketLogger::default(), Callback::default(), Arc::default())
+    }
+
+    fn open(options: &ConnectionBuilder,
+            logger: PacketLogger,
+            message_handler: Callback<Packet>,
+            stats: Arc<StatsCounters>)
+            -> Result<Self> {
+        let mut tcp_stream = options.target
+            .endpoint(options.port)
+            .connect(options.connect_timeout)?;
+        tcp_stream.set_write_timeout(options.socket_write_timeout)?;
+        let mut ntr_sender = NtrSender::new(tcp_stream.try_clone()?,
+                                            options.initial_seq,
+                                            logger.clone(),
+                                            stats.clone());
+        let mut handshake_rtt = None;
+        if options.handshake {
+            tcp_stream.set_read_timeout(Some(options.read_timeout))?;
+            handshake(&mut tcp_stream, &mut ntr_sender, &logger, options.max_data_len)?;
+            handshake_rtt = ntr_sender.take_heartbeat_sent().map(|sent| sent.elapsed());
+        }
+        // the threads retry reads that time out, so this only bounds how long the receiver
+        // goes without checking whether the connection is still wanted
+        tcp_stream.set_read_timeout(options.socket_read_timeout)?;
+        let pending: PendingResponses = Arc::new(Mutex::new(HashMap::new()));
+        let (process_list_tx, process_list_rx) = mpsc::channel();
+        let (memory_layout_tx, memory_layout_rx) = mpsc::channel();
+        let (hello_tx, hello_rx) = mpsc::channel();
+
+        let ntr_sender = Arc::new(Mutex::new(ntr_sender));
+        let shutdown_stream = tcp_stream.try_clone()?;
+        let connected = Arc::new(AtomicBool::new(true));
+        let last_heartbeat_ack = Arc::new(Mutex::new(Instant::now()));
+        let last_heartbeat_rtt = Arc::new(Mutex::new(handshake_rtt));
+        let heartbeat_paused = Arc::new(AtomicBool::new(false));
+        let heartbeat_error = Arc::new(Mutex::new(None));
 
-        let ntr_sender = Arc::new(Mutex::new(NtrSender::new(tcp_stream.try_clone()?)));
+        let mut threads = Vec::new();
 
         // spawn heartbeat thread
-        {
+        if let Some(interval) = options.heartbeat_interval {
             let ntr_sender = ntr_sender.clone();
-            thread::spawn(move || {
-                let one_second = time::Duration::seconds(1);
+            let connected = connected.clone();
+            let heartbeat_error = heartbeat_error.clone();
+            let paused = heartbeat_paused.clone();
+            let poll_interval = poll_interval(interval);
+            let interval = time::Duration::from_std(interval)
+                .unwrap_or_else(|_| time::Duration::max_value());
+            threads.push(thread::spawn(move || {
                 let mut heartbeat_sent_time = PreciseTime::now();
-                loop {
+                while connected.load(Ordering::SeqCst) {
                     let mut ntr_sender = ntr_sender.lock().unwrap();
-                    if heartbeat_sent_time.to(PreciseTime::now()) >= one_second &&
-                       ntr_sender.is_heartbeat_sendable() {
-                        ntr_sender.send_heartbeat_packet().unwrap();
+                    if heartbeat_sent_time.to(PreciseTime::now()) >= interval &&
+                       ntr_sender.is_heartbeat_sendable() &&
+                       !paused.load(Ordering::SeqCst) {
+                        if let Err(err) = ntr_sender.send_heartbeat_packet() {
+                            *heartbeat_error.lock().unwrap() = Some(err);
+                            connected.store(false, Ordering::SeqCst);
+                            break;
+                        }
                         heartbeat_sent_time = PreciseTime::now();
                         ntr_sender.set_is_heartbeat_sendable(false);
                     }
                     drop(ntr_sender);
-                    thread::sleep(Duration::from_millis(500));
+                    thread::sleep(poll_interval);
+                }
+            }));
+        }
+
+        // spawn idle timeout thread
+        if let Some(idle_timeout) = options.idle_timeout {
+            let ntr_sender = ntr_sender.clone();
+            let connected = connected.clone();
+            let pending = pending.clone();
+            let stream = tcp_stream.try_clone()?;
+            let poll_interval = poll_interval(idle_timeout);
+            threads.push(thread::spawn(move || {
+                while connected.load(Ordering::SeqCst) {
+                    let idle = ntr_sender.lock().unwrap().last_activity().elapsed();
+                    if idle >= idle_timeout && pending.lock().unwrap().is_empty() {
+                        // shutting down the socket also stops the receiver thread
+                        connected.store(false, Ordering::SeqCst);
+                        let _ = stream.shutdown(Shutdown::Both);
+                        break;
+                    }
+                    thread::sleep(poll_interval);
                 }
-            });
+            }));
         }
 
         // spawn receiver thread
+        //
+        // The thread exits when the socket fails or the Connection is dropped. Either way it
+        // clears `connected`, and dropping the pending responses' senders wakes up their waiters.
         {
             let ntr_sender = ntr_sender.clone();
-            thread::spawn(move || {
-                let mut buf = [0u8; 84];
+            let connected = connected.clone();
+            let pending = pending.clone();
+            let logger = logger.clone();
+            let message_handler = message_handler.clone();
+            let stats = stats.clone();
+            let last_heartbeat_ack = last_heartbeat_ack.clone();
+            let last_heartbeat_rtt = last_heartbeat_rtt.clone();
+            let max_data_len = options.max_data_len as usize;
+            threads.push(thread::spawn(move || {
+                let mut buf = [0u8; HEADER_LEN];
+                let mut process_list_text = OutputBuffer::new(PROCESS_LIST_END);
+                let mut memory_layout_text = OutputBuffer::new(MEMORY_LAYOUT_END);
                 loop {
-                    tcp_stream.read_exact(&mut buf).unwrap();
-                    let cmd = LittleEndian::read_u32(&buf[12..16]);
-                    let data_len = LittleEndian::read_u32(&buf[80..84]) as usize;
-
-                    if cmd == 0 {
-                        ntr_sender
-                            .lock()
-                            .unwrap()
-                            .set_is_heartbeat_sendable(true);
+                    if read_full(&mut tcp_stream, &mut buf, &connected).is_err() {
+                        break;
+                    }
+                    // without the magic number the stream has lost its place, and nothing after
+                    // this can be framed correctly, so the connection is dropped
+                    if !Packet::has_magic(&buf) {
+                        break;
+                    }
+                    let (mut packet, data_len) = Packet::from_header(&buf);
+                    logger.call(&PacketEvent {
+                                     direction: Direction::Received,
+                                     seq: packet.seq,
+                                     packet_type: packet.packet_type,
+                                     cmd: packet.cmd,
+                                     data_len: data_len as u32,
+                                 });
+
+                    if packet.cmd == 0 {
+                        let mut ntr_sender = ntr_sender.lock().unwrap();
+                        ntr_sender.set_is_heartbeat_sendable(true);
+                        if let Some(sent) = ntr_sender.take_heartbeat_sent() {
+                            *last_heartbeat_rtt.lock().unwrap() = Some(sent.elapsed());
+                        }
+                        drop(ntr_sender);
+                        *last_heartbeat_ack.lock().unwrap() = Instant::now();
+                    }
+                    // no reply is anywhere near this long, so the length is garbage and the
+                    // stream can't be trusted anymore
+                    if data_len > max_data_len {
+                        break;
                     }
                     if data_len != 0 {
-                        let mut data_buf = vec![0u8; data_len].into_boxed_slice();
-                        tcp_stream.read_exact(&mut data_buf).unwrap();
+                        packet.data = vec![0u8; data_len];
+                        if read_full(&mut tcp_stream, &mut packet.data, &connected).is_err() {
+                            break;
+                        }
+                    }
 
-                        if cmd == 0 {
-                            let msg = String::from_utf8_lossy(&data_buf);
-                            if let Some(_) = msg.find("end of process list.") {
-                                get_pid_tx.send(msg.into_owned()).unwrap();
+                    // replies to memory reads and raw requests go to whoever is waiting on them;
+                    // one that arrives after its request timed out is dropped
+                    if packet.cmd == 9 {
+                        stats.record_read_reply(packet.data.len());
+                    }
+                    let waiter = pending.lock().unwrap().remove(&packet.seq);
+                    if let Some((tx, sent)) = waiter {
+                        stats.record_rtt(sent.elapsed());
+                        let _ = tx.send(packet);
+                    } else if packet.cmd == 0 && !packet.data.is_empty() {
+                        let msg = String::from_utf8_lossy(&packet.data);
+                        if msg.contains("hello") && hello_tx.send(msg.to_string()).is_err() {
+                            break;
+                        }
+                        if let Some(list) = process_list_text.push(&msg) {
+                            if process_list_tx.send(list).is_err() {
+                                break;
+                            }
+                        }
+                        if let Some(layout) = memory_layout_text.push(&msg) {
+                            if memory_layout_tx.send(layout).is_err() {
+                                break;
                             }
-                        } else if cmd == 9 {
-                            mem_read_tx.send(data_buf).unwrap();
                         }
+                    } else if packet.cmd != 0 && packet.cmd != 9 {
+                        message_handler.call(&packet);
                     }
                 }
-            });
+                connected.store(false, Ordering::SeqCst);
+                pending.lock().unwrap().clear();
+            }));
         }
 
         Ok(Connection {
-               ntr_sender: ntr_sender,
-               mem_read_rx: mem_read_rx,
-               get_pid_rx: get_pid_rx,
+               link: Link {
+                   ntr_sender,
+                   connected,
+                   pending,
+                   validate_addresses: options.validate_addresses,
+                   chunk_len: options.chunk_len,
+               },
+               process_list_rx: Mutex::new(process_list_rx),
+               memory_layout_rx: Mutex::new(memory_layout_rx),
+               hello_rx: Mutex::new(hello_rx),
+               hello_reply: OnceLock::new(),
+               read_timeout: options.read_timeout,
+               heartbeat_interval: options.heartbeat_interval,
+               heartbeat_paused,
+               last_heartbeat_ack,
+               last_heartbeat_rtt,
+               heartbeat_error,
+               tcp_stream: shutdown_stream,
+               threads,
+               logger,
+               message_handler,
+               stats,
+               options: options.clone(),
            })
     }
 
+    /// Checks that NTR's debugger is answering on this connection.
+    ///
+    /// Sends NTR's hello packet and waits up to the connection's
+    /// [`read_timeout`](#method.read_timeout) for the reply, returning `Error::Timeout` if none
+    /// arrives. Opening a `Connection` already checks that heartbeats are answered, unless
+    /// [`ConnectionBuilder::handshake`](struct.ConnectionBuilder.html#method.handshake) turned
+    /// that off; this also records the reply for [`ntr_version`](#method.ntr_version).
+    ///
+    /// NTR sends the reply along with its next heartbeat response, so this only succeeds while
+    /// heartbeats are running.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use ntr::Connection;
+    ///
+    /// let connection = Connection::new("192.168.2.247").expect("couldn't connect");
+    /// connection.hello().expect("NTR isn't responding");
+    /// ```
+    pub fn hello(&self) -> Result<()> {
+        let reply = self.request_text(&self.hello_rx, NtrSender::send_hello_packet)?;
+        let _ = self.hello_reply.set(reply);
+        Ok(())
+    }
+
+    /// Returns the version number in NTR's reply to [`hello`](#method.hello), such as `"3.6"`.
+    ///
+    /// This is `None` until `hello` has succeeded, and also if the reply didn't contain a
+    /// version number, which is the case for NTR builds that just answer "hello". The first
+    /// thing in the reply that looks like a dotted version number is returned.
+    pub fn ntr_version(&self) -> Option<&str> {
+        static RE: OnceLock<Regex> = OnceLock::new();
+
+        let reply = self.hello_reply.get()?;
+        let re = RE.get_or_init(|| Regex::new(r"\d+(\.\d+)+").unwrap());
+        re.find(reply).map(|m| m.as_str())
+    }
+
+    /// Returns a handle for reading and writing the memory of the process with process id `pid`
+    /// without passing the id to every call.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use ntr::Connection;
+    ///
+    /// # let connection: Connection = unimplemented!();
+    /// let pid = connection.get_pid(0x0004000000187000).expect("pid not found");
+    /// let game = connection.attach(pid);
+    /// let hp = game.read_u32(0x8001000).expect("io error");
+    /// ```
+    pub fn attach(&self, pid: Pid) -> ProcessHandle<'_> {
+        ProcessHandle::new(self, pid)
+    }
+
+    /// Looks up the process running the title id `tid` and returns a handle bound to it.
+    ///
+    /// Returns `Error::PidNotFound` if the title isn't running. See
+    /// [`get_pid`](#method.get_pid) and [`attach`](#method.attach).
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use ntr::Connection;
+    ///
+    /// # let connection: Connection = unimplemented!();
+    /// let game = connection.attach_by_tid(0x0004000000187000).expect("pid not found");
+    /// let hp = game.read_u32(0x8001000).expect("io error");
+    /// ```
+    pub fn attach_by_tid(&self, tid: u64) -> Result<ProcessHandle<'_>> {
+        Ok(self.attach(self.get_pid(tid)?))
+    }
+
+    /// Tells NTR to reload its debugger.
+    ///
+    /// This can recover a debugger that has stopped behaving without restarting the tool using
+    /// it. Running processes aren't restarted, so process ids fetched earlier stay valid. NTR may
+    /// close this connection while it reloads; if later calls return `Error::Disconnected`, open
+    /// a new `Connection`.
+    pub fn reload(&self) -> Result<()> {
+        self.link.check_connected()?;
+        self.link.ntr_sender.lock().unwrap().send_reload_packet()?;
+        Ok(())
+    }
+
+    /// Returns the process list entry for the currently running title id `tid`, or `None` if no
+    /// running process has that title id.
+    ///
+    /// This is [`get_pid`](#method.get_pid) keeping the whole entry, so the process name can be
+    /// shown to confirm the right game was found without listing the processes again.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use ntr::Connection;
+    ///
+    /// # let connection: Connection = unimplemented!();
+    /// if let Some(process) = connection.get_process(0x0004000000126300).expect("io error") {
+    ///     println!("attaching to {} ({})", process.pname, process.pid);
+    /// }
+    /// ```
+    pub fn get_process(&self, tid: u64) -> Result<Option<ProcessInfo>> {
+        Ok(self.list_processes()?
+               .into_iter()
+               .find(|process| process.tid == tid))
+    }
+
     /// Returns the process identifier for the currently running title id `tid`.
     ///
-    /// You can find a list of title ids for 3DS games at [3dsdb](http://3dsdb.com/).
+    /// You can find a list of title ids for 3DS games at [3dsdb](http://3dsdb.com/). Title ids
+    /// are compared as numbers, so however NTR pads them in its process list doesn't matter.
+    /// Returns `Error::PidNotFound` if no running process has that title id, and
+    /// `Error::Timeout` if NTR doesn't send the process list within the connection's
+    /// [`read_timeout`](#method.read_timeout).
     ///
     /// # Examples
     ///
     /// ```no_run
     /// use ntr::Connection;
     ///
-    /// # let mut connection: Connection = unimplemented!();
-    /// let pid = connection.get_pid(0x0004000000126300u64)
+    /// # let connection: Connection = unimplemented!();
+    /// let pid = connection.get_pid(0x0004000000126300u64).expect("pid not found");
+    /// ```
+    pub fn get_pid(&self, tid: u64) -> Result<Pid> {
+        self.get_process(tid)?
+            .map(|process| process.pid)
+            .ok_or(Error::PidNotFound)
+    }
+
+    /// Waits for a process with title id `tid` to start, returning its process id, or `None` if
+    /// it hasn't started within `timeout`.
+    ///
+    /// The process list is checked twice a second, so a tool can be started before the game.
+    /// A process list that doesn't arrive in time, which can happen while a game is starting
+    /// up, is just checked again; other errors are returned right away. A check that's under
+    /// way when the time runs out is finished first, so this can take somewhat longer than
+    /// `timeout`. A `timeout` of `Duration::MAX` waits forever.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use ntr::Connection;
+    /// use std::time::Duration;
+    ///
+    /// # let connection: Connection = unimplemented!();
+    /// let pid = connection.wait_for_process(0x0004000000126300, Duration::from_secs(60))
     ///     .expect("io error")
-    ///     .expect("pid not found");
-    /// ```
-    pub fn get_pid(&mut self, tid: u64) -> io::Result<Option<u32>> {
-        self.ntr_sender
-            .lock()
-            .unwrap()
-            .send_list_process_packet()?;
-        let msg = self.get_pid_rx.recv().unwrap();
-        let cap = {
-            let mut re = r"pid: 0x([0-9a-fA-F]{8}), pname:[^,]*, tid: ".to_owned();
-            re.push_str(&format!("{:016x}", tid));
-            Regex::new(&re).unwrap().captures(&msg)
-        };
-        Ok(cap.and_then(|x| Some(u32::from_str_radix(x.get(1).unwrap().as_str(), 16).unwrap())))
+    ///     .expect("the game wasn't started");
+    /// ```
+    pub fn wait_for_process(&self, tid: u64, timeout: Duration) -> Result<Option<Pid>> {
+        // a timeout too long to add to the current time means waiting forever
+        let deadline = Instant::now().checked_add(timeout);
+        let poll_interval = Duration::from_millis(WAIT_FOR_PROCESS_POLL_MS);
+        loop {
+            match self.get_process(tid) {
+                Ok(Some(process)) => return Ok(Some(process.pid)),
+                Ok(None) | Err(Error::Timeout) => {}
+                Err(err) => return Err(err),
+            }
+            let sleep = match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Ok(None);
+                    }
+                    cmp::min(poll_interval, deadline - now)
+                }
+                None => poll_interval,
+            };
+            thread::sleep(sleep);
+        }
     }
 
-    /// Reads a chunk of 3DS memory.
+    /// Returns the process identifier of the first running process named `name`.
     ///
-    /// Reads `size` bytes of 3DS memory starting from address `addr` for the
-    /// process with process id `pid`.
-    pub fn mem_read(&mut self, addr: u32, size: u32, pid: u32) -> io::Result<Box<[u8]>> {
-        self.ntr_sender
-            .lock()
-            .unwrap()
-            .send_mem_read_packet(addr, size, pid)?;
-        Ok(self.mem_read_rx.recv().unwrap())
+    /// The name must match the process list's `pname` field exactly (the padding NTR adds is
+    /// ignored). This is handy when a game's title id differs between regions but its process
+    /// name doesn't. Returns `Error::PidNotFound` if no process has that name.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use ntr::Connection;
+    ///
+    /// # let connection: Connection = unimplemented!();
+    /// let pid = connection.get_pid_by_name("menu").expect("pid not found");
+    /// ```
+    pub fn get_pid_by_name(&self, name: &str) -> Result<Pid> {
+        self.list_processes()?
+            .into_iter()
+            .find(|process| process.pname == name)
+            .map(|process| process.pid)
+            .ok_or(Error::PidNotFound)
     }
 
-    /// Writes data to 3DS memory.
+    /// Returns the process id of the game or other application that's running, if there is one.
     ///
-    /// Writes `data` to the 3DS memory starting at address `addr` for the
-    /// process with process id `pid`.
-    pub fn mem_write(&mut self, addr: u32, data: &[u8], pid: u32) -> io::Result<usize> {
-        self.ntr_sender
-            .lock()
-            .unwrap()
-            .send_mem_write_packet(addr, pid, data)
+    /// NTR can't tell which process is in the foreground, so this picks from the process list
+    /// by title id, skipping system titles; see
+    /// [`ProcessInfo::is_application`](struct.ProcessInfo.html#method.is_application). Only one
+    /// application runs at a time, but if the list has more than one, the one started last is
+    /// returned. This is meant for quick one-off tools; use
+    /// [`get_pid`](#method.get_pid) when the title id is known.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use ntr::Connection;
+    ///
+    /// # let connection: Connection = unimplemented!();
+    /// let pid = connection.foreground_pid()
+    ///     .expect("couldn't list processes")
+    ///     .expect("no game is running");
+    /// ```
+    pub fn foreground_pid(&self) -> Result<Option<Pid>> {
+        // pids are handed out in increasing order, so the largest belongs to the newest process
+        Ok(self.list_processes()?
+               .into_iter()
+               .filter(ProcessInfo::is_application)
+               .map(|process| process.pid)
+               .max())
     }
 
-    /// Reads a `u32` from 3DS memory.
-    pub fn read_u32(&mut self, addr: u32, pid: u32) -> io::Result<u32> {
-        Ok(LittleEndian::read_u32(&self.mem_read(addr, 4, pid)?))
+    /// Returns every process currently running on the 3DS.
+    ///
+    /// Waits up to the connection's [`read_timeout`](#method.read_timeout) for the list,
+    /// returning `Error::Timeout` if it doesn't arrive. NTR sends the list along with its next
+    /// heartbeat response, so this only succeeds while heartbeats are running.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use ntr::Connection;
+    ///
+    /// # let connection: Connection = unimplemented!();
+    /// for process in connection.list_processes().expect("couldn't list processes") {
+    ///     println!("{} {:016x} {}", process.pid, process.tid, process.pname);
+    /// }
+    /// ```
+    pub fn list_processes(&self) -> Result<Vec<ProcessInfo>> {
+        let msg = self.request_text(&self.process_list_rx, NtrSender::send_list_process_packet)?;
+        Ok(parse_process_list(&msg))
     }
 
-    /// Reads a `u16` from 3DS memory.
-    pub fn read_u16(&mut self, addr: u32, pid: u32) -> io::Result<u16> {
-        Ok(LittleEndian::read_u16(&self.mem_read(addr, 2, pid)?))
+    /// Returns the mapped regions of the memory of the process with process id `pid`, in the
+    /// order NTR lists them.
+    ///
+    /// Scanning only these regions avoids reads of unmapped memory, which NTR never answers.
+    /// NTR doesn't report the regions' permissions. Waits up to the connection's
+    /// [`read_timeout`](#method.read_timeout) for the response, returning `Error::Timeout` if
+    /// it doesn't arrive. Like the process list, the response comes with NTR's text output, so
+    /// this needs heartbeats.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use ntr::Connection;
+    ///
+    /// # let connection: Connection = unimplemented!();
+    /// # let pid = ntr::Pid(0);
+    /// for region in connection.memory_regions(pid).expect("io error") {
+    ///     println!("{:08x}-{:08x}", region.base, region.end());
+    /// }
+    /// ```
+    pub fn memory_regions(&self, pid: Pid) -> Result<Vec<MemRegion>> {
+        let msg = self.request_text(&self.memory_layout_rx,
+                                    |ntr_sender| ntr_sender.send_memlayout_packet(pid.0))?;
+        Ok(parse_memory_layout(&msg))
     }
 
-    /// Reads a `u8` from 3DS memory.
-    pub fn read_u8(&mut self, addr: u32, pid: u32) -> io::Result<u8> {
-        Ok(self.mem_read(addr, 1, pid)?[0])
+    /// Sends a request with `send` and waits up to the read timeout for the text NTR answers it
+    /// with, which the receiver thread delivers on `rx`.
+    ///
+    /// `rx` stays locked until the answer arrives, so concurrent callers don't take each other's
+    /// answers, and an answer that arrived after an earlier call gave up is discarded first.
+    fn request_text<F>(&self, rx: &Mutex<Receiver<String>>, send: F) -> Result<String>
+        where F: FnOnce(&mut NtrSender) -> io::Result<()>
+    {
+        self.link.check_connected()?;
+        let rx = rx.lock().unwrap();
+        while rx.try_recv().is_ok() {}
+        send(&mut self.link.ntr_sender.lock().unwrap())?;
+        match rx.recv_timeout(self.read_timeout) {
+            Ok(text) => Ok(text),
+            Err(RecvTimeoutError::Timeout) => Err(Error::Timeout),
+            Err(RecvTimeoutError::Disconnected) => Err(Error::Disconnected),
+        }
     }
 
-    /// Reads an `i32` from 3DS memory.
-    pub fn read_i32(&mut self, addr: u32, pid: u32) -> io::Result<i32> {
-        Ok(LittleEndian::read_i32(&self.mem_read(addr, 4, pid)?))
+    /// Returns whether the connection is still alive.
+    ///
+    /// This is `false` once the socket has failed or closed. With heartbeats enabled, it's also
+    /// `false` while NTR hasn't answered one for two heartbeat intervals, which catches a 3DS
+    /// that dropped off the network without closing the socket. A poll loop can check this to
+    /// decide when to reconnect instead of waiting out a read timeout, and
+    /// [`take_heartbeat_error`](#method.take_heartbeat_error) tells whether a failed heartbeat
+    /// was the cause.
+    pub fn is_connected(&self) -> bool {
+        if !self.link.connected.load(Ordering::SeqCst) {
+            return false;
+        }
+        match self.heartbeat_interval {
+            Some(_) if self.is_heartbeat_paused() => true,
+            Some(interval) => {
+                // saturating, since an interval too long to double can never be missed twice
+                self.last_heartbeat_ack.lock().unwrap().elapsed() <= interval.saturating_mul(2)
+            }
+            None => true,
+        }
     }
 
-    /// Reads an `i16` from 3DS memory.
-    pub fn read_i16(&mut self, addr: u32, pid: u32) -> io::Result<i16> {
-        Ok(LittleEndian::read_i16(&self.mem_read(addr, 2, pid)?))
+    /// Returns how long NTR took to answer the most recent heartbeat, or `None` if no heartbeat
+    /// has been answered yet.
+    ///
+    /// Heartbeats go out continually, so this tracks the latency of the link without sending
+    /// anything extra, which makes it handy for a live latency display. Unless the handshake is
+    /// turned off, the first value comes from the heartbeat exchanged when opening the
+    /// connection. See
+    /// [`stats`](#method.stats) for the average round trip time of memory reads.
+    pub fn last_heartbeat_rtt(&self) -> Option<Duration> {
+        *self.last_heartbeat_rtt.lock().unwrap()
     }
 
-    /// Reads an `i8` from 3DS memory.
-    pub fn read_i8(&mut self, addr: u32, pid: u32) -> io::Result<i8> {
-        Ok(self.mem_read(addr, 1, pid)?[0] as i8)
+    /// Stops sending heartbeats until [`resume_heartbeat`](#method.resume_heartbeat) is called.
+    ///
+    /// This keeps the connection quiet apart from the packets sent by the caller, for example
+    /// while debugging packet ordering. A heartbeat that was already sent may still be answered.
+    /// Like with heartbeats disabled, [`list_processes`](#method.list_processes) and the other
+    /// calls relying on NTR's text output time out while heartbeats are paused, and
+    /// [`is_connected`](#method.is_connected) doesn't check for missing heartbeat replies.
+    /// Heartbeats start out running again after a `reconnect`.
+    pub fn pause_heartbeat(&self) {
+        self.heartbeat_paused.store(true, Ordering::SeqCst);
     }
 
-    /// Writes a `u32` to 3DS memory.
-    pub fn write_u32(&mut self, addr: u32, data: u32, pid: u32) -> io::Result<()> {
-        let buf = &mut vec![0u8; 4];
-        LittleEndian::write_u32(buf, data);
-        self.mem_write(addr, buf, pid).map(|_| ())
+    /// Starts sending heartbeats again after [`pause_heartbeat`](#method.pause_heartbeat).
+    pub fn resume_heartbeat(&self) {
+        // the pause isn't counted as NTR failing to answer
+        *self.last_heartbeat_ack.lock().unwrap() = Instant::now();
+        self.heartbeat_paused.store(false, Ordering::SeqCst);
     }
 
-    /// Writes a `u16` to 3DS memory.
-    pub fn write_u16(&mut self, addr: u32, data: u16, pid: u32) -> io::Result<()> {
-        let buf = &mut vec![0u8; 2];
-        LittleEndian::write_u16(buf, data);
-        self.mem_write(addr, buf, pid).map(|_| ())
+    /// Returns `true` if heartbeats are paused.
+    pub fn is_heartbeat_paused(&self) -> bool {
+        self.heartbeat_paused.load(Ordering::SeqCst)
     }
 
-    /// Writes a `u8` to 3DS memory.
-    pub fn write_u8(&mut self, addr: u32, data: u8, pid: u32) -> io::Result<()> {
-        self.mem_write(addr, &[data], pid).map(|_| ())
+    /// Returns the error that stopped the heartbeat thread, if sending a heartbeat failed, and
+    /// clears it.
+    ///
+    /// The heartbeat thread stops at the first error and marks the connection as closed, so
+    /// there's at most one error per connection; calling `reconnect` starts over with none.
+    /// Errors on the receiving side aren't reported here.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use ntr::Connection;
+    ///
+    /// # let connection: Connection = unimplemented!();
+    /// if !connection.is_connected() {
+    ///     if let Some(err) = connection.take_heartbeat_error() {
+    ///         eprintln!("heartbeat failed: {}", err);
+    ///     }
+    /// }
+    /// ```
+    pub fn take_heartbeat_error(&self) -> Option<io::Error> {
+        self.heartbeat_error.lock().unwrap().take()
     }
 
-    /// Writes an `i32` to 3DS memory.
-    pub fn write_i32(&mut self, addr: u32, data: i32, pid: u32) -> io::Result<()> {
-        let buf = &mut vec![0u8; 4];
-        LittleEndian::write_i32(buf, data);
-        self.mem_write(addr, buf, pid).map(|_| ())
+    /// Closes the connection and opens a new one to the same address with the same options.
+    ///
+    /// The read timeout, packet logger, message handler and stats carry over, and sequence
+    /// numbers continue from where the old connection left off. Memory reads still waiting on
+    /// the old connection fail with `Error::Disconnected`, and `FreezeHandle`s started on it stop
+    /// writing.
+    ///
+    /// `Connection` never reconnects on its own; use this together with `is_connected` to pick
+    /// a policy, or `reconnect_with_backoff` to keep retrying. If reconnecting fails, the
+    /// connection stays disconnected and `reconnect` can be called again.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use ntr::Connection;
+    ///
+    /// let mut connection = Connection::new("192.168.2.247").expect("couldn't connect");
+    /// loop {
+    ///     if !connection.is_connected() {
+    ///         connection.reconnect().expect("couldn't reconnect");
+    ///     }
+    ///     // ...
+    /// }
+    /// ```
+    pub fn reconnect(&mut self) -> Result<()> {
+        let mut options = self.options.clone();
+        options.read_timeout = self.read_timeout;
+        options.initial_seq = self.current_seq();
+
+        // NTR serves one client at a time, so let go of the old socket before dialing again
+        self.link.connected.store(false, Ordering::SeqCst);
+        let _ = self.tcp_stream.shutdown(Shutdown::Both);
+
+        *self = Connection::open(&options,
+                                 self.logger.clone(),
+                                 self.message_handler.clone(),
+                                 self.stats.clone())?;
+        Ok(())
     }
 
-    /// Writes an `i16` to 3DS memory.
-    pub fn write_i16(&mut self, addr: u32, data: i16, pid: u32) -> io::Result<()> {
-        let buf = &mut vec![0u8; 2];
-        LittleEndian::write_i16(buf, data);
-        self.mem_write(addr, buf, pid).map(|_| ())
+    /// Reconnects like [`reconnect`](#method.reconnect), then returns which of the process ids
+    /// in `pids` still belong to running processes.
+    ///
+    /// After a brief disconnect, like the 3DS going to sleep, the game is usually still running
+    /// under the same process id. Rather than fetching the process list again, each id is
+    /// checked by reading a word at the start of its code segment, waiting up to a second for
+    /// the answer; an id that doesn't answer is left out. Process ids aren't reused, so an id
+    /// that answers still belongs to the same process. `ProcessHandle`s can't be kept across
+    /// the reconnect, but new ones can be made from the returned ids with
+    /// [`attach`](#method.attach).
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use ntr::Connection;
+    ///
+    /// # let mut connection: Connection = unimplemented!();
+    /// # let game_pid = ntr::Pid(0);
+    /// let still_running = connection.reconnect_preserving_pids(&[game_pid]).expect("io error");
+    /// if still_running.is_empty() {
+    ///     println!("the game was closed");
+    /// }
+    /// ```
+    pub fn reconnect_preserving_pids(&mut self, pids: &[Pid]) -> Result<Vec<Pid>> {
+        self.reconnect()?;
+        let timeout = Duration::from_millis(PID_CHECK_TIMEOUT_MS);
+        let mut alive = Vec::with_capacity(pids.len());
+        for &pid in pids {
+            match self.mem_read_timeout(regions::CODE_BASE, 4, pid, timeout) {
+                Ok(_) => alive.push(pid),
+                Err(Error::Timeout) | Err(Error::EmptyResponse) => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(alive)
     }
 
-    /// Writes an `i8` to 3DS memory.
-    pub fn write_i8(&mut self, addr: u32, data: i8, pid: u32) -> io::Result<()> {
-        self.mem_write(addr, &[data as u8], pid).map(|_| ())
+    /// Calls `reconnect` up to `max_attempts` times until it succeeds, sleeping between attempts.
+    ///
+    /// The first sleep lasts `initial_delay`, and each one after that is twice as long as the one
+    /// before; no sleep lasts longer than 30 seconds, the first one included. Returns the last
+    /// attempt's error if every attempt fails. This is meant for riding out the 3DS going to
+    /// sleep or briefly leaving the network.
+    pub fn reconnect_with_backoff(&mut self,
+                                  max_attempts: u32,
+                                  initial_delay: Duration)
+                                  -> Result<()> {
+        let max_delay = Duration::from_secs(MAX_RECONNECT_DELAY_SECS);
+        let mut delay = cmp::min(initial_delay, max_delay);
+        let mut result = Err(Error::Disconnected);
+        for attempt in 0..max_attempts {
+            if attempt != 0 {
+                thread::sleep(delay);
+                delay = cmp::min(delay.checked_mul(2).unwrap_or(max_delay), max_delay);
+            }
+            result = self.reconnect();
+            if result.is_ok() {
+                break;
+            }
+        }
+        result
+    }
+
+    /// Returns the sequence number the next packet sent on this connection will carry.
+    ///
+    /// Useful for matching this connection's traffic up with a packet capture.
+    pub fn current_seq(&self) -> u32 {
+        self.link.ntr_sender.lock().unwrap().current_seq()
+    }
+
+    /// Sets a callback that's called with the header of every packet sent or received.
+    ///
+    /// This replaces any logger set before. The callback runs on whichever thread sent or
+    /// received the packet, including the connection's background threads, so it should return
+    /// quickly.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use ntr::Connection;
+    ///
+    /// # let connection: Connection = unimplemented!();
+    /// connection.set_packet_logger(|event| {
+    ///     println!("{:?} cmd {} seq {} ({} bytes)",
+    ///              event.direction,
+    ///              event.cmd,
+    ///              event.seq,
+    ///              event.data_len);
+    /// });
+    /// ```
+    pub fn set_packet_logger<F>(&self, logger: F)
+        where F: Fn(&PacketEvent) + Send + Sync + 'static
+    {
+        self.logger.set(Some(Box::new(logger)));
+    }
+
+    /// Removes the packet logger, if one is set.
+    pub fn clear_packet_logger(&self) {
+        self.logger.set(None);
+    }
+
+    /// Installs a callback for packets from NTR that nothing else handles.
+    ///
+    /// Heartbeat replies, which carry NTR's text output like the process list, and replies to
+    /// memory reads and raw requests are all consumed by the connection. Any other packet NTR
+    /// sends on its own, such as debug output from a plugin or an NTR build using its own
+    /// command ids, is passed to `handler` instead of being dropped. This replaces any handler
+    /// set before. The handler runs on the receiving thread, so it should return quickly; a
+    /// handler that blocks holds up every response.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use ntr::Connection;
+    ///
+    /// # let connection: Connection = unimplemented!();
+    /// connection.on_message(|packet| {
+    ///     println!("cmd {}: {}", packet.cmd, String::from_utf8_lossy(&packet.data));
+    /// });
+    /// ```
+    pub fn on_message<F>(&self, handler: F)
+        where F: Fn(&Packet) + Send + Sync + 'static
+    {
+        self.message_handler.set(Some(Box::new(handler)));
+    }
+
+    /// Removes the callback set with [`on_message`](#method.on_message), if one is set.
+    pub fn clear_on_message(&self) {
+        self.message_handler.set(None);
+    }
+
+    /// Returns counters describing the traffic on this connection so far: the number of reads
+    /// and writes, the bytes transferred, and the average round-trip time.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use ntr::Connection;
+    ///
+    /// # let connection: Connection = unimplemented!();
+    /// let stats = connection.stats();
+    /// println!("{} reads, {} bytes, average rtt {:?}",
+    ///          stats.reads,
+    ///          stats.bytes_read,
+    ///          stats.average_rtt);
+    /// ```
+    pub fn stats(&self) -> ConnectionStats {
+        self.stats.snapshot()
+    }
+
+    /// Returns how long reads wait for a response before giving up.
+    pub fn read_timeout(&self) -> Duration {
+        self.read_timeout
+    }
+
+    /// Sets how long reads wait for a response before giving up.
+    ///
+    /// The default is 10 seconds, or whatever was chosen with
+    /// [`ConnectionBuilder::read_timeout`](struct.ConnectionBuilder.html#method.read_timeout).
+    pub fn set_read_timeout(&mut self, timeout: Duration) {
+        self.read_timeout = timeout;
+    }
+
+    /// Reads a chunk of 3DS memory.
+    ///
+    /// Reads `size` bytes of 3DS memory starting from address `addr` for the
+    /// process with process id `pid`. This waits for at most the connection's
+    /// [`read_timeout`](#method.read_timeout).
+    ///
+    /// Any size can be requested. At most 64 KiB is requested per packet, or less if set with
+    /// [`ConnectionBuilder::chunk_size`](struct.ConnectionBuilder.html#method.chunk_size); larger
+    /// reads are split into chunks that are all requested up front, then reassembled in order.
+    /// An address or size of zero is rejected before anything is sent; see
+    /// `ConnectionBuilder::validate_addresses`. A chunk NTR answers with no data is requested
+    /// once more before the read fails with `Error::EmptyResponse`.
+    pub fn mem_read(&self, addr: u32, size: u32, pid: Pid) -> Result<Box<[u8]>> {
+        let timeout = self.read_timeout;
+        self.mem_read_timeout(addr, size, pid, timeout)
+    }
+
+    /// Reads a chunk of 3DS memory, waiting at most `timeout` for the data to arrive.
+    ///
+    /// For reads split into several packets, `timeout` applies to each chunk. Returns
+    /// `Error::Timeout` if NTR doesn't respond in time, and `Error::Parse` if a response isn't
+    /// the size that was requested.
+    pub fn mem_read_timeout(&self,
+                            addr: u32,
+                            size: u32,
+                            pid: Pid,
+                            timeout: Duration)
+                            -> Result<Box<[u8]>> {
+        self.read_vec_timeout(addr, size, pid, timeout).map(Vec::into_boxed_slice)
+    }
+
+    /// Reads a chunk of 3DS memory using only 4-byte aligned requests.
+    ///
+    /// The start of the read is rounded down and the end rounded up to a multiple of 4, and
+    /// just the requested `size` bytes starting at `addr` are returned. NTR copies memory byte
+    /// by byte and doesn't require aligned reads itself, so [`mem_read`](#method.mem_read) is
+    /// the better choice normally; this is for memory, like some I/O mappings, that only
+    /// gives correct data to word-sized accesses. As the rounded range starts at 0 for `addr`
+    /// below 4, those addresses are rejected like an address of zero.
+    pub fn mem_read_aligned(&self, addr: u32, size: u32, pid: Pid) -> Result<Box<[u8]>> {
+        match aligned_read(addr, size) {
+            Some((start, len, offset)) => {
+                let data = self.mem_read(start, len, pid)?;
+                Ok(data[offset..offset + size as usize].into())
+            }
+            None => self.mem_read(addr, size, pid),
+        }
+    }
+
+    /// Sends a request to read a chunk of 3DS memory without waiting for the data.
+    ///
+    /// The returned token is passed to [`try_mem_read`](#method.try_mem_read) to check whether
+    /// the data has arrived, which suits an event loop that can't block. The request is split
+    /// into chunks like [`mem_read`](#method.mem_read)'s. There's no timeout; if the data never
+    /// arrives, `try_mem_read` keeps returning `None` until the token is dropped.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use ntr::Connection;
+    ///
+    /// # let connection: Connection = unimplemented!();
+    /// # let (addr, pid) = (0, ntr::Pid(0));
+    /// let mut token = connection.mem_read_start(addr, 0x100, pid).expect("io error");
+    /// loop {
+    ///     if let Some(data) = connection.try_mem_read(&mut token).expect("io error") {
+    ///         println!("{:?}", data);
+    ///         break;
+    ///     }
+    ///     // ... do other work ...
+    /// }
+    /// ```
+    pub fn mem_read_start(&self, addr: u32, size: u32, pid: Pid) -> Result<ReadToken> {
+        let mut ntr_sender = self.link.ntr_sender.lock().unwrap();
+        let reads = self.link.send_mem_read(&mut ntr_sender, addr, size, pid)?;
+        Ok(ReadToken::new(reads))
+    }
+
+    /// Returns the data for a read started with [`mem_read_start`](#method.mem_read_start) if
+    /// all of it has arrived, or `None` if it hasn't yet. Never blocks.
+    ///
+    /// Once the data has been returned, the token is used up, and calling this again with it
+    /// returns an `InvalidInput` error. Returns `Error::Disconnected` if the connection is lost
+    /// before the data arrives.
+    pub fn try_mem_read(&self, token: &mut ReadToken) -> Result<Option<Box<[u8]>>> {
+        Ok(token.poll()?.map(Vec::into_boxed_slice))
+    }
+
+    /// Reads a chunk of 3DS memory into a `Vec`.
+    ///
+    /// This is the same as [`mem_read`](#method.mem_read), but hands back the `Vec` the data was
+    /// received into instead of converting it to a boxed slice, so callers that want a `Vec`
+    /// don't need to copy it.
+    pub fn read_vec(&self, addr: u32, size: u32, pid: Pid) -> Result<Vec<u8>> {
+        let timeout = self.read_timeout;
+        self.read_vec_timeout(addr, size, pid, timeout)
+    }
+
+    fn read_vec_timeout(&self,
+                        addr: u32,
+                        size: u32,
+                        pid: Pid,
+                        timeout: Duration)
+                        -> Result<Vec<u8>> {
+        self.link.read(addr, size, pid, timeout)
+    }
+
+    /// Reads several chunks of 3DS memory at once.
+    ///
+    /// Each `(addr, size)` pair in `requests` is read like [`mem_read`](#method.mem_read) would,
+    /// and the chunks are returned in the same order. All the read requests are sent before any
+    /// response is waited on, so this takes roughly one round trip instead of one per request.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use ntr::Connection;
+    ///
+    /// # let connection: Connection = unimplemented!();
+    /// # let pid = ntr::Pid(0);
+    /// let chunks = connection.mem_read_many(&[(0x8000000, 4), (0x8001000, 16)], pid)
+    ///     .expect("io error");
+    /// ```
+    pub fn mem_read_many(&self, requests: &[(u32, u32)], pid: Pid) -> Result<Vec<Box<[u8]>>> {
+        let reads = {
+            let mut ntr_sender = self.link.ntr_sender.lock().unwrap();
+            let mut reads = Vec::with_capacity(requests.len());
+            for &(addr, size) in requests {
+                reads.push(self.link.send_mem_read(&mut ntr_sender, addr, size, pid)?);
+            }
+            reads
+        };
+        let timeout = self.read_timeout;
+        reads
+            .into_iter()
+            .map(|chunks| self.link.wait_mem_read(chunks, timeout).map(Vec::into_boxed_slice))
+            .collect()
+    }
+
+    /// Reads 3DS memory into a caller-provided buffer.
+    ///
+    /// Reads `buf.len()` bytes starting from address `addr` for the process with process id
+    /// `pid`, letting a polling loop reuse one buffer instead of collecting a new one from every
+    /// call.
+    ///
+    /// The buffer is filled in order, a megabyte at a time, with the packets of each megabyte
+    /// requested together. Only that much is held in memory besides `buf` itself, so
+    /// `buf` can be as large as the region being read, such as a memory-mapped file for
+    /// dumping a whole heap. If a chunk fails, the error is returned and the part of `buf`
+    /// before it has already been filled.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use ntr::Connection;
+    ///
+    /// # let connection: Connection = unimplemented!();
+    /// # let pid = ntr::Pid(0);
+    /// let mut heap = vec![0u8; 0x1000000];
+    /// connection.read_into(0x8000000, &mut heap, pid).expect("io error");
+    /// ```
+    pub fn read_into(&self, addr: u32, buf: &mut [u8], pid: Pid) -> Result<()> {
+        if buf.len() > u32::MAX as usize {
+            return Err(Error::Io(io::Error::new(io::ErrorKind::InvalidInput,
+                                                "buffer is larger than the address space")));
+        }
+        // checked up front so a bad range fails before any of `buf` is touched
+        self.link.validate(addr, buf.len() as u32)?;
+        for (i, window) in buf.chunks_mut(READ_INTO_WINDOW_LEN).enumerate() {
+            let window_addr = addr.wrapping_add((i * READ_INTO_WINDOW_LEN) as u32);
+            let data = self.link.read(window_addr, window.len() as u32, pid, self.read_timeout)?;
+            window.copy_from_slice(&data);
+        }
+        Ok(())
+    }
+
+    /// Reads `N` bytes of 3DS memory into a fixed-size array.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use ntr::Connection;
+    ///
+    /// # let connection: Connection = unimplemented!();
+    /// # let (addr, pid) = (0, ntr::Pid(0));
+    /// let header: [u8; 16] = connection.read_array(addr, pid).expect("io error");
+    /// ```
+    pub fn read_array<const N: usize>(&self, addr: u32, pid: Pid) -> Result<[u8; N]> {
+        let mut buf = [0u8; N];
+        self.read_into(addr, &mut buf, pid)?;
+        Ok(buf)
+    }
+
+    /// Copies a region of 3DS memory to a writer.
+    ///
+    /// Reads `size` bytes starting from address `addr` 64 KiB at a time, writing each piece to
+    /// `out` as it arrives, so a large region never has to fit in memory at once. Returns the
+    /// number of bytes written. Each piece is read like [`mem_read`](#method.mem_read) does,
+    /// so the packets sent follow the builder's `chunk_size`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use ntr::Connection;
+    /// use std::fs::File;
+    ///
+    /// # let connection: Connection = unimplemented!();
+    /// # let pid = ntr::Pid(0);
+    /// let mut file = File::create("heap.bin").expect("couldn't create file");
+    /// connection.dump_region(0x8000000, 0x1000000, pid, &mut file).expect("dump failed");
+    /// ```
+    pub fn dump_region<W: Write>(&self,
+                                 addr: u32,
+                                 size: u32,
+                                 pid: Pid,
+                                 out: &mut W)
+                                 -> Result<u64> {
+        let mut written = 0;
+        while written < size {
+            let chunk_len = cmp::min(CHUNK_LEN, size - written);
+            let chunk = self.mem_read(addr.wrapping_add(written), chunk_len, pid)?;
+            out.write_all(&chunk)?;
+            written += chunk_len;
+        }
+        Ok(written as u64)
+    }
+
+    /// Copies the contents of a reader into 3DS memory, the reverse of
+    /// [`dump_region`](#method.dump_region).
+    ///
+    /// Reads `src` to the end 64 KiB at a time, writing each piece to the next addresses
+    /// starting from `addr`, so a large dump never has to fit in memory at once. Returns the
+    /// number of bytes written. Each piece is written like [`mem_write`](#method.mem_write)
+    /// does, so the packets sent follow the builder's `chunk_size`. An error stops the copy
+    /// partway, with the pieces before it already written.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use ntr::Connection;
+    /// use std::fs::File;
+    ///
+    /// # let connection: Connection = unimplemented!();
+    /// # let pid = ntr::Pid(0);
+    /// let mut file = File::open("heap.bin").expect("couldn't open file");
+    /// connection.write_region(0x8000000, &mut file, pid).expect("restore failed");
+    /// ```
+    pub fn write_region<R: Read>(&self, addr: u32, src: &mut R, pid: Pid) -> Result<u64> {
+        let mut buf = vec![0u8; CHUNK_LEN as usize];
+        let mut written = 0u64;
+        loop {
+            let len = read_up_to(src, &mut buf)?;
+            if len == 0 {
+                return Ok(written);
+            }
+            let offset = written as u32;
+            if written > u32::MAX as u64 {
+                return Err(Error::InvalidAddress { base: addr, offset });
+            }
+            self.mem_write(offset_addr(addr, offset)?, &buf[..len], pid)?;
+            written += len as u64;
+        }
+    }
+
+    /// Returns a reader over the memory of the process with process id `pid`, starting at
+    /// `addr`.
+    ///
+    /// See [`MemReader`](struct.MemReader.html).
+    pub fn mem_reader(&self, addr: u32, pid: Pid) -> MemReader<'_> {
+        MemReader::new(self, addr, pid)
+    }
+
+    /// Returns a writer into the memory of the process with process id `pid`, starting at
+    /// `addr`.
+    ///
+    /// See [`MemWriter`](struct.MemWriter.html).
+    pub fn mem_writer(&self, addr: u32, pid: Pid) -> MemWriter<'_> {
+        MemWriter::new(self, addr, pid)
+    }
+
+    /// Hashes a region of 3DS memory.
+    ///
+    /// Reads `size` bytes starting from address `addr`, like
+    /// [`dump_region`](#method.dump_region) does, and returns their 64-bit FNV-1a hash (offset
+    /// basis `0xcbf29ce484222325`, prime `0x100000001b3`). The algorithm won't change, so hashes
+    /// can be saved and compared across runs to tell cheaply whether a region changed, without
+    /// keeping a copy of it.
+    pub fn region_hash(&self, addr: u32, size: u32, pid: Pid) -> Result<u64> {
+        let mut hasher = Fnv1a(FNV_OFFSET_BASIS);
+        self.dump_region(addr, size, pid, &mut hasher)?;
+        Ok(hasher.0)
+    }
+
+    /// Writes data to 3DS memory.
+    ///
+    /// Writes `data` to the 3DS memory starting at address `addr` for the
+    /// process with process id `pid`.
+    ///
+    /// At most 64 KiB is sent per packet, or less if set with
+    /// [`ConnectionBuilder::chunk_size`](struct.ConnectionBuilder.html#method.chunk_size); larger
+    /// writes are split into consecutive chunks, sent back to back. Returns `data.len()`; if any
+    /// part of a packet can't be sent, an error is returned instead.
+    ///
+    /// NTR doesn't acknowledge writes, so success only means the request was sent; the write is
+    /// applied or silently dropped on the 3DS side. Use
+    /// [`mem_write_verify`](#method.mem_write_verify) when that matters.
+    pub fn mem_write(&self, addr: u32, data: &[u8], pid: Pid) -> Result<usize> {
+        self.link.validate_write(addr, data)?;
+        self.link.check_connected()?;
+        let mut ntr_sender = self.link.ntr_sender.lock().unwrap();
+        self.link.send_mem_write(&mut ntr_sender, addr, data, pid)?;
+        Ok(data.len())
+    }
+
+    /// Writes data to 3DS memory, then reads it back to confirm the write was applied.
+    ///
+    /// Returns `Error::VerifyFailed` if the memory read back doesn't match `data`. The game can
+    /// change the memory between the write and the read, so a value it rewrites every frame
+    /// can fail verification even though the write went through.
+    pub fn mem_write_verify(&self, addr: u32, data: &[u8], pid: Pid) -> Result<()> {
+        self.mem_write(addr, data, pid)?;
+        if *self.mem_read(addr, data.len() as u32, pid)? == *data {
+            Ok(())
+        } else {
+            Err(Error::VerifyFailed)
+        }
+    }
+
+    /// Reads a value of any type implementing `FromMemBytes` from 3DS memory.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use ntr::Connection;
+    ///
+    /// # let connection: Connection = unimplemented!();
+    /// # let (addr, pid) = (0, ntr::Pid(0));
+    /// let hp: u32 = connection.read(addr, pid).expect("io error");
+    /// ```
+    pub fn read<T: FromMemBytes>(&self, addr: u32, pid: Pid) -> Result<T> {
+        Ok(T::from_le_bytes(&self.mem_read(addr, T::SIZE as u32, pid)?))
+    }
+
+    /// Reads a big-endian value of any type implementing `FromMemBytes` from 3DS memory.
+    ///
+    /// The 3DS itself is little-endian, but some data, like network-order fields a game copied
+    /// into memory verbatim, is stored big-endian.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use ntr::Connection;
+    ///
+    /// # let connection: Connection = unimplemented!();
+    /// # let (addr, pid) = (0, ntr::Pid(0));
+    /// let port = connection.read_be::<u16>(addr, pid).expect("io error");
+    /// ```
+    pub fn read_be<T: FromMemBytes>(&self, addr: u32, pid: Pid) -> Result<T> {
+        Ok(T::from_be_bytes(&self.mem_read(addr, T::SIZE as u32, pid)?))
+    }
+
+    /// Reads a value whose type is chosen at runtime.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use ntr::{Connection, ValueType};
+    ///
+    /// # let connection: Connection = unimplemented!();
+    /// # let (addr, pid) = (0, ntr::Pid(0));
+    /// let value = connection.read_value(addr, ValueType::F32, pid).expect("io error");
+    /// println!("{}", value);
+    /// ```
+    pub fn read_value(&self, addr: u32, ty: ValueType, pid: Pid) -> Result<Value> {
+        Ok(ty.decode(&self.mem_read(addr, ty.size(), pid)?))
+    }
+
+    /// Reads every field of the structure at `base` described by `layout`, keyed by field name.
+    ///
+    /// Only the bytes between the first and last field are fetched, with a single
+    /// [`mem_read`](#method.mem_read). A layout with no fields reads nothing. Fails with
+    /// `Error::InvalidAddress` if the fields would lie past `0xFFFFFFFF`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use ntr::{Connection, StructLayout, ValueType};
+    ///
+    /// # let connection: Connection = unimplemented!();
+    /// # let (player_addr, pid) = (0, ntr::Pid(0));
+    /// let layout = StructLayout::new()
+    ///     .field("hp", 0x10, ValueType::U32)
+    ///     .field("max_hp", 0x14, ValueType::U32)
+    ///     .field("x", 0x40, ValueType::F32);
+    /// let player = connection.read_struct(player_addr, &layout, pid).expect("io error");
+    /// println!("hp: {}/{}", player["hp"], player["max_hp"]);
+    /// ```
+    pub fn read_struct(&self,
+                       base: u32,
+                       layout: &StructLayout,
+                       pid: Pid)
+                       -> Result<HashMap<String, Value>> {
+        let range = layout.range();
+        if range.start == range.end {
+            return Ok(HashMap::new());
+        }
+        let addr = offset_addr(base, range.start)?;
+        offset_addr(base, range.end - 1)?;
+        let data = self.mem_read(addr, range.end - range.start, pid)?;
+        Ok(layout.decode(&data))
+    }
+
+    /// Reads `count` consecutive values of any type implementing `FromMemBytes` from 3DS memory.
+    ///
+    /// The whole array is fetched with a single [`mem_read`](#method.mem_read) and then
+    /// decoded, which is much faster than reading the values one at a time. Fails with an
+    /// `InvalidInput` error if the array is larger than the address space.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use ntr::Connection;
+    ///
+    /// # let connection: Connection = unimplemented!();
+    /// # let (addr, pid) = (0, ntr::Pid(0));
+    /// let inventory: Vec<u16> = connection.read_slice(addr, 32, pid).expect("io error");
+    /// ```
+    pub fn read_slice<T: FromMemBytes>(&self, addr: u32, count: u32, pid: Pid) -> Result<Vec<T>> {
+        let size = count
+            .checked_mul(T::SIZE as u32)
+            .ok_or_else(|| {
+                            Error::Io(io::Error::new(io::ErrorKind::InvalidInput,
+                                                     "array is larger than the address space"))
+                        })?;
+        let data = self.mem_read(addr, size, pid)?;
+        Ok(data.chunks_exact(T::SIZE).map(T::from_le_bytes).collect())
+    }
+
+    /// Reads `count` consecutive `u32`s from 3DS memory.
+    ///
+    /// See [`read_slice`](#method.read_slice) for other value types.
+    pub fn read_u32_slice(&self, addr: u32, count: u32, pid: Pid) -> Result<Vec<u32>> {
+        self.read_slice(addr, count, pid)
+    }
+
+    /// Reads a `u64` from 3DS memory.
+    pub fn read_u64(&self, addr: u32, pid: Pid) -> Result<u64> {
+        Ok(LittleEndian::read_u64(&self.mem_read(addr, 8, pid)?))
+    }
+
+    /// Reads a `u32` from 3DS memory.
+    pub fn read_u32(&self, addr: u32, pid: Pid) -> Result<u32> {
+        Ok(LittleEndian::read_u32(&self.mem_read(addr, 4, pid)?))
+    }
+
+    /// Reads a 3-byte unsigned integer from 3DS memory.
+    ///
+    /// Some games pack colors or counters into 3 bytes; the value is zero-extended to a `u32`.
+    pub fn read_u24(&self, addr: u32, pid: Pid) -> Result<u32> {
+        Ok(LittleEndian::read_uint(&self.mem_read(addr, 3, pid)?, 3) as u32)
+    }
+
+    /// Reads a `u16` from 3DS memory.
+    pub fn read_u16(&self, addr: u32, pid: Pid) -> Result<u16> {
+        Ok(LittleEndian::read_u16(&self.mem_read(addr, 2, pid)?))
+    }
+
+    /// Reads a `u8` from 3DS memory.
+    pub fn read_u8(&self, addr: u32, pid: Pid) -> Result<u8> {
+        Ok(self.mem_read(addr, 1, pid)?[0])
+    }
+
+    /// Reads an `i64` from 3DS memory.
+    pub fn read_i64(&self, addr: u32, pid: Pid) -> Result<i64> {
+        Ok(LittleEndian::read_i64(&self.mem_read(addr, 8, pid)?))
+    }
+
+    /// Reads an `i32` from 3DS memory.
+    pub fn read_i32(&self, addr: u32, pid: Pid) -> Result<i32> {
+        Ok(LittleEndian::read_i32(&self.mem_read(addr, 4, pid)?))
+    }
+
+    /// Reads an `i16` from 3DS memory.
+    pub fn read_i16(&self, addr: u32, pid: Pid) -> Result<i16> {
+        Ok(LittleEndian::read_i16(&self.mem_read(addr, 2, pid)?))
+    }
+
+    /// Reads an `i8` from 3DS memory.
+    pub fn read_i8(&self, addr: u32, pid: Pid) -> Result<i8> {
+        Ok(self.mem_read(addr, 1, pid)?[0] as i8)
+    }
+
+    /// Reads an `f32` from 3DS memory.
+    pub fn read_f32(&self, addr: u32, pid: Pid) -> Result<f32> {
+        Ok(LittleEndian::read_f32(&self.mem_read(addr, 4, pid)?))
+    }
+
+    /// Reads an `f64` from 3DS memory.
+    pub fn read_f64(&self, addr: u32, pid: Pid) -> Result<f64> {
+        Ok(LittleEndian::read_f64(&self.mem_read(addr, 8, pid)?))
+    }
+
+    /// Reads a one-byte flag from 3DS memory; zero is `false` and anything else is `true`.
+    pub fn read_bool(&self, addr: u32, pid: Pid) -> Result<bool> {
+        Ok(self.read_u8(addr, pid)? != 0)
+    }
+
+    /// Reads a signed 32-bit fixed-point number with `frac_bits` fractional bits from 3DS
+    /// memory.
+    ///
+    /// The stored `i32` is scaled by 2<sup>-`frac_bits`</sup>, so a 20.12 number is read with
+    /// a `frac_bits` of 12 and a 16.16 number with 16.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use ntr::Connection;
+    ///
+    /// # let connection: Connection = unimplemented!();
+    /// # let (addr, pid) = (0, ntr::Pid(0));
+    /// let speed = connection.read_fixed(addr, 12, pid).expect("io error");
+    /// ```
+    pub fn read_fixed(&self, addr: u32, frac_bits: u32, pid: Pid) -> Result<f64> {
+        Ok(self.read_i32(addr, pid)? as f64 / fixed_scale(frac_bits))
+    }
+
+    /// Reads a null-terminated UTF-8 string from 3DS memory.
+    ///
+    /// Reads `max_len` bytes starting at `addr` and returns everything before the first `0x00`
+    /// byte. If there is no terminator within `max_len` bytes, all `max_len` bytes are returned.
+    /// Invalid UTF-8 sequences are replaced with `U+FFFD`.
+    pub fn read_cstring(&self, addr: u32, max_len: u32, pid: Pid) -> Result<String> {
+        let data = self.mem_read(addr, max_len, pid)?;
+        let len = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+        Ok(String::from_utf8_lossy(&data[..len]).into_owned())
+    }
+
+    /// Reads a null-terminated UTF-16LE string from 3DS memory.
+    ///
+    /// Reads `max_units` 16-bit code units starting at `addr` and returns everything before the
+    /// first null unit. If there is no terminator within `max_units` units, all of them are
+    /// returned. Unpaired surrogates are replaced with `U+FFFD`.
+    pub fn read_utf16_string(&self, addr: u32, max_units: u32, pid: Pid) -> Result<String> {
+        let size = max_units
+            .checked_mul(2)
+            .ok_or_else(|| {
+                            Error::Io(io::Error::new(io::ErrorKind::InvalidInput,
+                                                     "string is larger than the address space"))
+                        })?;
+        let data = self.mem_read(addr, size, pid)?;
+        let units: Vec<u16> = data.chunks_exact(2)
+            .map(LittleEndian::read_u16)
+            .take_while(|&unit| unit != 0)
+            .collect();
+        Ok(String::from_utf16_lossy(&units))
+    }
+
+    /// Writes a value of any type implementing `ToMemBytes` to 3DS memory.
+    pub fn write<T: ToMemBytes>(&self, addr: u32, data: T, pid: Pid) -> Result<()> {
+        self.mem_write(addr, &data.to_le_bytes(), pid).map(|_| ())
+    }
+
+    /// Writes a value of any type implementing `ToMemBytes` to 3DS memory in big-endian byte
+    /// order.
+    pub fn write_be<T: ToMemBytes>(&self, addr: u32, data: T, pid: Pid) -> Result<()> {
+        self.mem_write(addr, &data.to_be_bytes(), pid).map(|_| ())
+    }
+
+    /// Writes a value whose type is chosen at runtime, as many bytes as its type occupies.
+    pub fn write_value(&self, addr: u32, value: Value, pid: Pid) -> Result<()> {
+        self.write(addr, value, pid)
+    }
+
+    /// Writes consecutive values of any type implementing `ToMemBytes` to 3DS memory.
+    ///
+    /// The values are serialized into one buffer and sent with a single
+    /// [`mem_write`](#method.mem_write), which only splits it up if it's over the chunk size.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use ntr::Connection;
+    ///
+    /// # let connection: Connection = unimplemented!();
+    /// # let (addr, pid) = (0, ntr::Pid(0));
+    /// let saved: Vec<u16> = connection.read_slice(addr, 32, pid).expect("io error");
+    /// // ... lose some items in game ...
+    /// connection.write_slice(addr, &saved, pid).expect("io error");
+    /// ```
+    pub fn write_slice<T: ToMemBytes>(&self, addr: u32, data: &[T], pid: Pid) -> Result<()> {
+        let mut buf = Vec::new();
+        for value in data {
+            buf.extend_from_slice(&value.to_le_bytes());
+        }
+        self.mem_write(addr, &buf, pid).map(|_| ())
+    }
+
+    /// Writes consecutive `u32`s to 3DS memory.
+    ///
+    /// See [`write_slice`](#method.write_slice) for other value types.
+    pub fn write_u32_slice(&self, addr: u32, data: &[u32], pid: Pid) -> Result<()> {
+        self.write_slice(addr, data, pid)
+    }
+
+    /// Writes a `u64` to 3DS memory.
+    pub fn write_u64(&self, addr: u32, data: u64, pid: Pid) -> Result<()> {
+        let buf = &mut [0u8; 8];
+        LittleEndian::write_u64(buf, data);
+        self.mem_write(addr, buf, pid).map(|_| ())
+    }
+
+    /// Writes a `u32` to 3DS memory.
+    pub fn write_u32(&self, addr: u32, data: u32, pid: Pid) -> Result<()> {
+        let buf = &mut [0u8; 4];
+        LittleEndian::write_u32(buf, data);
+        self.mem_write(addr, buf, pid).map(|_| ())
+    }
+
+    /// Writes a `u32` to 3DS memory unless it's already there, returning whether it was written.
+    ///
+    /// The current value is read first, which costs a round trip but saves the write in a loop
+    /// that keeps a value in place and only rarely finds it changed. The read and the write
+    /// aren't atomic: the game can change the value in between, and that change is overwritten.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use ntr::Connection;
+    ///
+    /// # let connection: Connection = unimplemented!();
+    /// # let (addr, pid) = (0, ntr::Pid(0));
+    /// if connection.write_u32_if_changed(addr, 999, pid).expect("io error") {
+    ///     println!("corrected the value");
+    /// }
+    /// ```
+    pub fn write_u32_if_changed(&self, addr: u32, data: u32, pid: Pid) -> Result<bool> {
+        if self.read_u32(addr, pid)? == data {
+            return Ok(false);
+        }
+        self.write_u32(addr, data, pid)?;
+        Ok(true)
+    }
+
+    /// Writes the low 3 bytes of `data` to 3DS memory.
+    ///
+    /// Fails with an `Error::Io` of kind `InvalidInput`, without writing anything, if `data`
+    /// doesn't fit in 3 bytes.
+    pub fn write_u24(&self, addr: u32, data: u32, pid: Pid) -> Result<()> {
+        if data > 0xFF_FFFF {
+            return Err(Error::Io(io::Error::new(io::ErrorKind::InvalidInput,
+                                                "value doesn't fit in 3 bytes")));
+        }
+        let buf = &mut [0u8; 3];
+        LittleEndian::write_uint(buf, data as u64, 3);
+        self.mem_write(addr, buf, pid).map(|_| ())
+    }
+
+    /// Writes a `u16` to 3DS memory.
+    pub fn write_u16(&self, addr: u32, data: u16, pid: Pid) -> Result<()> {
+        let buf = &mut [0u8; 2];
+        LittleEndian::write_u16(buf, data);
+        self.mem_write(addr, buf, pid).map(|_| ())
+    }
+
+    /// Writes a `u8` to 3DS memory.
+    pub fn write_u8(&self, addr: u32, data: u8, pid: Pid) -> Result<()> {
+        self.mem_write(addr, &[data], pid).map(|_| ())
+    }
+
+    /// Writes an `i64` to 3DS memory.
+    pub fn write_i64(&self, addr: u32, data: i64, pid: Pid) -> Result<()> {
+        let buf = &mut [0u8; 8];
+        LittleEndian::write_i64(buf, data);
+        self.mem_write(addr, buf, pid).map(|_| ())
+    }
+
+    /// Writes an `i32` to 3DS memory.
+    pub fn write_i32(&self, addr: u32, data: i32, pid: Pid) -> Result<()> {
+        let buf = &mut [0u8; 4];
+        LittleEndian::write_i32(buf, data);
+        self.mem_write(addr, buf, pid).map(|_| ())
+    }
+
+    /// Writes an `i16` to 3DS memory.
+    pub fn write_i16(&self, addr: u32, data: i16, pid: Pid) -> Result<()> {
+        let buf = &mut [0u8; 2];
+        LittleEndian::write_i16(buf, data);
+        self.mem_write(addr, buf, pid).map(|_| ())
+    }
+
+    /// Writes an `i8` to 3DS memory.
+    pub fn write_i8(&self, addr: u32, data: i8, pid: Pid) -> Result<()> {
+        self.mem_write(addr, &[data as u8], pid).map(|_| ())
+    }
+
+    /// Writes an `f32` to 3DS memory.
+    pub fn write_f32(&self, addr: u32, data: f32, pid: Pid) -> Result<()> {
+        let buf = &mut [0u8; 4];
+        LittleEndian::write_f32(buf, data);
+        self.mem_write(addr, buf, pid).map(|_| ())
+    }
+
+    /// Writes an `f64` to 3DS memory.
+    pub fn write_f64(&self, addr: u32, data: f64, pid: Pid) -> Result<()> {
+        let buf = &mut [0u8; 8];
+        LittleEndian::write_f64(buf, data);
+        self.mem_write(addr, buf, pid).map(|_| ())
+    }
+
+    /// Writes a one-byte flag to 3DS memory, as 1 for `true` and 0 for `false`.
+    pub fn write_bool(&self, addr: u32, data: bool, pid: Pid) -> Result<()> {
+        self.write_u8(addr, data as u8, pid)
+    }
+
+    /// Writes `value` to 3DS memory as a signed 32-bit fixed-point number with `frac_bits`
+    /// fractional bits, rounded to the nearest representable number.
+    ///
+    /// This is the counterpart of [`read_fixed`](#method.read_fixed). Fails with an `Error::Io`
+    /// of kind `InvalidInput`, without writing anything, if `value` doesn't fit.
+    pub fn write_fixed(&self, addr: u32, value: f64, frac_bits: u32, pid: Pid) -> Result<()> {
+        let raw = (value * fixed_scale(frac_bits)).round();
+        if !(raw >= i32::MIN as f64 && raw <= i32::MAX as f64) {
+            return Err(Error::Io(io::Error::new(io::ErrorKind::InvalidInput,
+                                                "value doesn't fit the fixed-point format")));
+        }
+        self.write_i32(addr, raw as i32, pid)
+    }
+
+    /// Keeps `value` frozen at `addr` by writing it every `interval` from a background thread.
+    ///
+    /// An `interval` shorter than 1 millisecond, including zero, is treated as 1 millisecond.
+    /// The freeze lasts until the returned handle is dropped or stopped, or the connection is
+    /// lost. The address is checked like [`mem_write`](#method.mem_write) checks it before the
+    /// freeze starts, and values too large for one packet are written in several.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use ntr::Connection;
+    /// use std::time::Duration;
+    ///
+    /// # let connection: Connection = unimplemented!();
+    /// # let (addr, pid) = (0, ntr::Pid(0));
+    /// let freeze = connection.freeze(addr, 9999u32, pid, Duration::from_millis(50))
+    ///     .expect("invalid address");
+    /// // ... play the game ...
+    /// freeze.stop();
+    /// ```
+    pub fn freeze<T: ToMemBytes>(&self,
+                                 addr: u32,
+                                 value: T,
+                                 pid: Pid,
+                                 interval: Duration)
+                                 -> Result<FreezeHandle> {
+        let data = value.to_le_bytes();
+        self.link.validate_write(addr, &data)?;
+        Ok(FreezeHandle::spawn(self.link.clone(), addr, data, pid, interval))
+    }
+
+    /// Keeps the `u32` `value` frozen at `addr`, rewriting it every 100 milliseconds.
+    ///
+    /// See [`freeze`](#method.freeze) for control over the interval and other value types.
+    pub fn freeze_u32(&self, addr: u32, value: u32, pid: Pid) -> Result<FreezeHandle> {
+        self.freeze(addr,
+                    value,
+                    pid,
+                    Duration::from_millis(DEFAULT_FREEZE_INTERVAL_MS))
+    }
+
+    /// Starts a background thread that keeps any number of values frozen, writing all of them
+    /// every `interval`.
+    ///
+    /// The set starts out empty; see [`FreezeSet`](struct.FreezeSet.html) for adding values.
+    /// Trainers that freeze more than a few values should prefer this over a
+    /// [`freeze`](#method.freeze) per value, which takes a thread each. As with `freeze`, an
+    /// `interval` under 1 millisecond is treated as 1 millisecond.
+    pub fn freeze_set(&self, interval: Duration) -> FreezeSet {
+        FreezeSet::spawn(self.link.clone(), interval)
+    }
+
+    /// Watches the value of type `T` at `addr`, calling `callback` with the new value whenever it
+    /// changes.
+    ///
+    /// A background thread reads the value every `interval` and compares it to the previous
+    /// read; the first read only records the starting value. A read that times out, or that
+    /// NTR still answers with no data after the retry, is skipped. The watch lasts until the
+    /// returned handle is dropped or stopped, or the connection is lost. `callback` runs on the
+    /// background thread.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use ntr::Connection;
+    /// use std::time::Duration;
+    ///
+    /// # let connection: Connection = unimplemented!();
+    /// # let (addr, pid) = (0, ntr::Pid(0));
+    /// let watch = connection.watch(addr, pid, Duration::from_millis(50), |hp: u32| {
+    ///     println!("hp is now {}", hp);
+    /// });
+    /// // ... play the game ...
+    /// watch.stop();
+    /// ```
+    pub fn watch<T, F>(&self, addr: u32, pid: Pid, interval: Duration, callback: F) -> WatchHandle
+        where T: FromMemBytes + PartialEq + Clone + Send + 'static,
+              F: FnMut(T) + Send + 'static
+    {
+        WatchHandle::spawn(self.link.clone(),
+                           addr,
+                           pid,
+                           interval,
+                           self.read_timeout,
+                           callback)
+    }
+
+    /// Watches the `u32` at `addr`, calling `callback` with the new value whenever it changes.
+    ///
+    /// See [`watch`](#method.watch) for other value types.
+    pub fn watch_u32<F>(&self, addr: u32, pid: Pid, interval: Duration, callback: F) -> WatchHandle
+        where F: FnMut(u32) + Send + 'static
+    {
+        self.watch(addr, pid, interval, callback)
+    }
+
+    /// Reads the value of type `T` at `addr` every `interval` on a background thread, recording
+    /// each value with the time it was read.
+    ///
+    /// Unlike [`watch`](#method.watch), every read is kept, which suits logging how a value
+    /// changes over time. Reads are scheduled at fixed intervals from the start, and a read
+    /// that times out is skipped. An `interval` shorter than 1 millisecond, including zero, is
+    /// treated as 1 millisecond. Sampling lasts until the returned sampler is stopped or
+    /// dropped, or the connection is lost.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use ntr::Connection;
+    /// use std::thread;
+    /// use std::time::Duration;
+    ///
+    /// # let connection: Connection = unimplemented!();
+    /// # let (addr, pid) = (0, ntr::Pid(0));
+    /// let sampler = connection.sample::<u32>(addr, pid, Duration::from_millis(16));
+    /// thread::sleep(Duration::from_secs(10));
+    /// for sample in sampler.stop() {
+    ///     println!("{:?} {}", sample.time, sample.value);
+    /// }
+    /// ```
+    pub fn sample<T>(&self, addr: u32, pid: Pid, interval: Duration) -> Sampler<T>
+        where T: FromMemBytes + Send + 'static
+    {
+        Sampler::spawn(self.link.clone(), addr, pid, interval, self.read_timeout)
+    }
+
+    /// Sends an arbitrary packet to NTR, returning the sequence number it was sent with.
+    ///
+    /// This is an escape hatch for NTR commands this crate doesn't wrap. See
+    /// [`Packet`](struct.Packet.html) for the header layout; the sequence number, magic number
+    /// and data length are filled in automatically. Use
+    /// [`raw_request`](#method.raw_request) instead to wait for NTR's reply.
+    pub fn send_raw_packet(&self,
+                           packet_type: u32,
+                           cmd: u32,
+                           args: [u32; 16],
+                           data: &[u8])
+                           -> Result<u32> {
+        self.link.check_connected()?;
+        let mut ntr_sender = self.link.ntr_sender.lock().unwrap();
+        let seq = ntr_sender.current_seq();
+        ntr_sender.send_raw_packet(packet_type, cmd, &args, data)?;
+        Ok(seq)
+    }
+
+    /// Sends an arbitrary packet to NTR and waits up to the connection's
+    /// [`read_timeout`](#method.read_timeout) for the reply with the same sequence number.
+    ///
+    /// Only use this for commands NTR replies to directly, otherwise it always times out.
+    /// Commands whose output NTR prints as text, like the process list, are answered through
+    /// heartbeat replies instead.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use ntr::Connection;
+    ///
+    /// # let connection: Connection = unimplemented!();
+    /// # let pid = ntr::Pid(0);
+    /// // the same memory read `read_u32` performs
+    /// let mut args = [0u32; 16];
+    /// args[0] = pid.0;
+    /// args[1] = 0x8000000;
+    /// args[2] = 4;
+    /// let reply = connection.raw_request(0, 9, args, &[]).expect("io error");
+    /// println!("{:?}", reply.data);
+    /// ```
+    pub fn raw_request(&self,
+                       packet_type: u32,
+                       cmd: u32,
+                       args: [u32; 16],
+                       data: &[u8])
+                       -> Result<Packet> {
+        let response = {
+            let mut ntr_sender = self.link.ntr_sender.lock().unwrap();
+            let response = self.link.register_response(&ntr_sender)?;
+            ntr_sender.send_raw_packet(packet_type, cmd, &args, data)?;
+            response
+        };
+        response.wait(self.read_timeout)
+    }
+
+    /// Reads a pointer stored in 3DS memory, returning the address it points to.
+    ///
+    /// This reads the `u32` at `addr` like [`read_u32`](#method.read_u32), but treats it as an
+    /// address to be dereferenced: a null pointer fails with `Error::NullPointer` instead of
+    /// being returned, so it can't turn into a read of address zero that never gets an answer.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use ntr::Connection;
+    ///
+    /// # let connection: Connection = unimplemented!();
+    /// # let pid = ntr::Pid(0);
+    /// let monster = connection.read_pointer(0x83343A4, pid).expect("io error");
+    /// let hp = connection.read_u32_off(monster, 0x1318, pid).expect("io error");
+    /// ```
+    pub fn read_pointer(&self, addr: u32, pid: Pid) -> Result<u32> {
+        match self.read_u32(addr, pid)? {
+            0 => Err(Error::NullPointer { addr }),
+            ptr => Ok(ptr),
+        }
+    }
+
+    /// Follows a chain of pointers, returning the final address.
+    ///
+    /// Reads the pointer stored at `base` and adds the first offset to it, then reads the pointer
+    /// stored at that address and adds the second offset, and so on. The last offset is added
+    /// but not dereferenced, so the result is the address of the value at the end of the chain,
+    /// `[[base] + offsets[0]] + offsets[1]` for two offsets. With no offsets, `base` is returned
+    /// unchanged. Pointers read during a loading screen are often null or garbage, so a null
+    /// pointer along the chain fails with `Error::NullPointer`, and an offset that takes an
+    /// address past `0xFFFFFFFF` fails with `Error::InvalidAddress` instead of panicking or
+    /// wrapping around.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use ntr::Connection;
+    ///
+    /// # let connection: Connection = unimplemented!();
+    /// # let pid = ntr::Pid(0);
+    /// let health_addr = connection.follow_pointer_chain(0x83343A4, &[0x1318], pid)
+    ///     .expect("io error");
+    /// ```
+    pub fn follow_pointer_chain(&self, base: u32, offsets: &[u32], pid: Pid) -> Result<u32> {
+        let mut addr = base;
+        for &offset in offsets {
+            addr = offset_addr(self.read_pointer(addr, pid)?, offset)?;
+        }
+        Ok(addr)
+    }
+
+    /// Follows a chain of pointers like [`follow_pointer_chain`](#method.follow_pointer_chain),
+    /// then reads the `u32` at the final address.
+    ///
+    /// The chain is followed again on every call, so this keeps working when the game moves the
+    /// structure being pointed into. An error from any read along the chain is returned as is.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use ntr::Connection;
+    ///
+    /// # let connection: Connection = unimplemented!();
+    /// # let pid = ntr::Pid(0);
+    /// let health = connection.read_u32_via(0x83343A4, &[0x1318], pid).expect("io error");
+    /// ```
+    pub fn read_u32_via(&self, base: u32, offsets: &[u32], pid: Pid) -> Result<u32> {
+        let addr = self.follow_pointer_chain(base, offsets, pid)?;
+        self.read_u32(addr, pid)
+    }
+
+    /// Follows a chain of pointers like [`follow_pointer_chain`](#method.follow_pointer_chain),
+    /// then writes the `u32` `data` to the final address.
+    ///
+    /// See [`read_u32_via`](#method.read_u32_via).
+    pub fn write_u32_via(&self, base: u32, offsets: &[u32], data: u32, pid: Pid) -> Result<()> {
+        let addr = self.follow_pointer_chain(base, offsets, pid)?;
+        self.write_u32(addr, data, pid)
+    }
+
+    /// Reads `size` bytes at `offset` bytes past `base`.
+    ///
+    /// This is [`mem_read`](#method.mem_read) on `base + offset`, for reading the fields of a
+    /// structure whose address is known. If the sum is past `0xFFFFFFFF`, which a garbage base
+    /// pointer can easily cause, this fails with `Error::InvalidAddress` instead of panicking.
+    pub fn mem_read_off(&self, base: u32, offset: u32, size: u32, pid: Pid) -> Result<Box<[u8]>> {
+        self.mem_read(offset_addr(base, offset)?, size, pid)
+    }
+
+    /// Writes `data` at `offset` bytes past `base`. See [`mem_read_off`](#method.mem_read_off).
+    pub fn mem_write_off(&self, base: u32, offset: u32, data: &[u8], pid: Pid) -> Result<usize> {
+        self.mem_write(offset_addr(base, offset)?, data, pid)
+    }
+
+    /// Reads a value of any type implementing `FromMemBytes` at `offset` bytes past `base`. See
+    /// [`mem_read_off`](#method.mem_read_off).
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use ntr::Connection;
+    ///
+    /// # let connection: Connection = unimplemented!();
+    /// # let pid = ntr::Pid(0);
+    /// let monster = connection.read_u32(0x83343A4, pid).expect("io error");
+    /// let hp: u32 = connection.read_off(monster, 0x1318, pid).expect("io error");
+    /// ```
+    pub fn read_off<T: FromMemBytes>(&self, base: u32, offset: u32, pid: Pid) -> Result<T> {
+        self.read(offset_addr(base, offset)?, pid)
+    }
+
+    /// Writes a value of any type implementing `ToMemBytes` at `offset` bytes past `base`. See
+    /// [`mem_read_off`](#method.mem_read_off).
+    pub fn write_off<T: ToMemBytes>(&self,
+                                    base: u32,
+                                    offset: u32,
+                                    data: T,
+                                    pid: Pid)
+                                    -> Result<()> {
+        self.write(offset_addr(base, offset)?, data, pid)
+    }
+
+    /// Reads the `u32` at `offset` bytes past `base`. See [`mem_read_off`](#method.mem_read_off).
+    pub fn read_u32_off(&self, base: u32, offset: u32, pid: Pid) -> Result<u32> {
+        self.read_off(base, offset, pid)
+    }
+
+    /// Writes the `u32` `data` at `offset` bytes past `base`. See
+    /// [`mem_read_off`](#method.mem_read_off).
+    pub fn write_u32_off(&self, base: u32, offset: u32, data: u32, pid: Pid) -> Result<()> {
+        self.write_off(base, offset, data, pid)
+    }
+}
+
+/// Returns the factor between a fixed-point number with `frac_bits` fractional bits and its raw
+/// integer.
+fn fixed_scale(frac_bits: u32) -> f64 {
+    2f64.powi(frac_bits as i32)
+}
+
+/// Widens a read of `size` bytes at `addr` to 4-byte boundaries, returning the address and size
+/// to request and where the requested bytes start in what comes back.
+///
+/// Returns `None` if the read is aligned already, and also if it can't be widened inside the
+/// address space, leaving `mem_read` to read or reject it as it is.
+fn aligned_read(addr: u32, size: u32) -> Option<(u32, u32, usize)> {
+    let start = addr as u64 & !(READ_ALIGN - 1);
+    let end = (addr as u64 + size as u64 + READ_ALIGN - 1) & !(READ_ALIGN - 1);
+    let aligned = start == addr as u64 && end == addr as u64 + size as u64;
+    if aligned || size == 0 || end > 1 << 32 || end - start > u32::MAX as u64 {
+        return None;
+    }
+    Some((start as u32, (end - start) as u32, (addr as u64 - start) as usize))
+}
+
+/// Adds `offset` to `base`, failing with `Error::InvalidAddress` on overflow.
+fn offset_addr(base: u32, offset: u32) -> Result<u32> {
+    base.checked_add(offset).ok_or(Error::InvalidAddress { base, offset })
+}
+
+/// Reads from `src` until `buf` is full or the end of the input is reached, returning how many
+/// bytes were read.
+fn read_up_to<R: Read>(src: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut len = 0;
+    while len < buf.len() {
+        match src.read(&mut buf[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(len)
+}
+
+/// Sends a heartbeat and checks that the reply is an NTR packet, before any background thread is
+/// reading from the socket.
+fn handshake(tcp_stream: &mut TcpStream,
+             ntr_sender: &mut NtrSender,
+             logger: &PacketLogger,
+             max_data_len: u32)
+             -> Result<()> {
+    ntr_sender.send_heartbeat_packet()?;
+    let mut buf = [0u8; HEADER_LEN];
+    tcp_stream.read_exact(&mut buf).map_err(handshake_error)?;
+    if !Packet::has_magic(&buf) {
+        return Err(Error::ProtocolMismatch);
+    }
+    let (packet, data_len) = Packet::from_header(&buf);
+    logger.call(&PacketEvent {
+                     direction: Direction::Received,
+                     seq: packet.seq,
+                     packet_type: packet.packet_type,
+                     cmd: packet.cmd,
+                     data_len: data_len as u32,
+                 });
+    if packet.cmd != 0 || data_len > max_data_len as usize {
+        return Err(Error::ProtocolMismatch);
+    }
+
+    // the heartbeat's text output is skipped; nothing can be waiting for it yet
+    let skipped = io::copy(&mut (&*tcp_stream).take(data_len as u64), &mut io::sink())
+        .map_err(handshake_error)?;
+    if skipped != data_len as u64 {
+        return Err(Error::Disconnected);
+    }
+    Ok(())
+}
+
+/// Turns the error from waiting for the handshake reply into the error `connect` returns.
+fn handshake_error(err: io::Error) -> Error {
+    match err.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => Error::Timeout,
+        io::ErrorKind::UnexpectedEof => Error::Disconnected,
+        _ => Error::Io(err),
+    }
+}
+
+/// How long a background thread that acts after `interval` sleeps between checks.
+fn poll_interval(interval: Duration) -> Duration {
+    interval.clamp(Duration::from_millis(MIN_POLL_INTERVAL_MS),
+                   Duration::from_millis(MAX_POLL_INTERVAL_MS))
+}
+
+/// Fills `buf` from `stream`, like `read_exact`.
+///
+/// Interrupted and timed out reads are retried for as long as `connected` is set instead of
+/// failing, and keep what was read so far, so a packet can't get split by a transient error.
+/// The end of the stream is reported as `UnexpectedEof`.
+fn read_full(stream: &mut TcpStream, mut buf: &mut [u8], connected: &AtomicBool) -> io::Result<()> {
+    while !buf.is_empty() {
+        match stream.read(buf) {
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => buf = &mut buf[n..],
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted ||
+                          e.kind() == io::ErrorKind::WouldBlock ||
+                          e.kind() == io::ErrorKind::TimedOut => {
+                if !connected.load(Ordering::SeqCst) {
+                    return Err(io::ErrorKind::NotConnected.into());
+                }
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+/// A 64-bit FNV-1a hasher that bytes can be written into.
+struct Fnv1a(u64);
+
+impl Write for Fnv1a {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &byte in buf {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(FNV_PRIME);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for Connection {
+    /// Closes the socket and waits for the background threads to exit.
+    fn drop(&mut self) {
+        self.link.connected.store(false, Ordering::SeqCst);
+        let _ = self.tcp_stream.shutdown(Shutdown::Both);
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fnv1a(chunks: &[&[u8]]) -> u64 {
+        let mut hasher = Fnv1a(FNV_OFFSET_BASIS);
+        for chunk in chunks {
+            hasher.write_all(chunk).unwrap();
+        }
+        hasher.0
+    }
+
+    #[test]
+    fn fnv1a_matches_published_vectors() {
+        assert_eq!(fnv1a(&[]), 0xcbf29ce484222325);
+        assert_eq!(fnv1a(&[b"a"]), 0xaf63dc4c8601ec8c);
+        assert_eq!(fnv1a(&[b"foobar"]), 0x85944171f73967e8);
+    }
+
+    #[test]
+    fn fnv1a_ignores_how_data_is_split() {
+        assert_eq!(fnv1a(&[b"foo", b"", b"bar"]), fnv1a(&[b"foobar"]));
+    }
+
+    #[test]
+    fn aligned_read_unaligned_start() {
+        assert_eq!(aligned_read(0x1001, 3), Some((0x1000, 4, 1)));
+        assert_eq!(aligned_read(0x1003, 4), Some((0x1000, 8, 3)));
+    }
+
+    #[test]
+    fn aligned_read_unaligned_end() {
+        assert_eq!(aligned_read(0x1000, 1), Some((0x1000, 4, 0)));
+        assert_eq!(aligned_read(0x1000, 6), Some((0x1000, 8, 0)));
+    }
+
+    #[test]
+    fn aligned_read_already_aligned() {
+        assert_eq!(aligned_read(0x1000, 4), None);
+        assert_eq!(aligned_read(0x1000, 0x10000), None);
+        assert_eq!(aligned_read(0x1001, 0), None);
+    }
+
+    #[test]
+    fn aligned_read_at_top_of_address_space() {
+        assert_eq!(aligned_read(0xFFFF_FFFE, 2), Some((0xFFFF_FFFC, 4, 2)));
+        assert_eq!(aligned_read(0xFFFF_FFFD, 1), Some((0xFFFF_FFFC, 4, 1)));
+        assert_eq!(aligned_read(0xFFFF_FFFC, 4), None);
+
+        // these run past the end, so mem_read gets them as they are
+        assert_eq!(aligned_read(0xFFFF_FFFF, 2), None);
+        assert_eq!(aligned_read(1, u32::MAX), None);
     }
 }
@@ -0,0 +1,363 @@
+use crate::error::{Error, Result};
+use crate::ntr_sender::NtrSender;
+use std::cmp;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::mem;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::time::{Duration, Instant};
+use crate::{Packet, Pid};
+
+/// Senders for the requests waiting on a response, along with when each request was sent, keyed
+/// by sequence number.
+pub(crate) type PendingResponses = Arc<Mutex<HashMap<u32, (Sender<Packet>, Instant)>>>;
+
+/// A request that has been sent but not answered yet.
+///
+/// Dropping it unregisters the request, so a response that arrives later is discarded.
+pub(crate) struct PendingResponse {
+    seq: u32,
+    rx: Receiver<Packet>,
+    pending: PendingResponses,
+}
+
+impl PendingResponse {
+    /// Waits up to `timeout` for the response.
+    pub(crate) fn wait(self, timeout: Duration) -> Result<Packet> {
+        match self.rx.recv_timeout(timeout) {
+            Ok(packet) => Ok(packet),
+            Err(RecvTimeoutError::Timeout) => Err(Error::Timeout),
+            Err(RecvTimeoutError::Disconnected) => Err(Error::Disconnected),
+        }
+    }
+
+    /// Returns the response if it has arrived, without waiting.
+    fn try_wait(&self) -> Result<Option<Packet>> {
+        match self.rx.try_recv() {
+            Ok(packet) => Ok(Some(packet)),
+            Err(TryRecvError::Empty) => Ok(None),
+            Err(TryRecvError::Disconnected) => Err(Error::Disconnected),
+        }
+    }
+}
+
+impl Drop for PendingResponse {
+    fn drop(&mut self) {
+        self.pending.lock().unwrap().remove(&self.seq);
+    }
+}
+
+/// A memory read request that has been sent but not answered yet.
+pub(crate) struct PendingRead {
+    response: PendingResponse,
+    addr: u32,
+    size: u32,
+    pid: Pid,
+}
+
+impl PendingRead {
+    /// Waits up to `timeout` for the data.
+    fn wait(self, timeout: Duration) -> Result<Vec<u8>> {
+        let size = self.size;
+        check_size(size, self.response.wait(timeout)?.data)
+    }
+
+    /// Returns the data if it has arrived, without waiting.
+    fn try_wait(&self) -> Result<Option<Vec<u8>>> {
+        match self.response.try_wait()? {
+            Some(packet) => check_size(self.size, packet.data).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Checks that a memory read response holds the `size` bytes that were requested.
+pub(crate) fn check_size(size: u32, data: Vec<u8>) -> Result<Vec<u8>> {
+    if data.is_empty() && size != 0 {
+        return Err(Error::EmptyResponse);
+    }
+    if data.len() != size as usize {
+        return Err(Error::Parse(format!("expected {} bytes of memory, got {}", size, data.len())));
+    }
+    Ok(data)
+}
+
+/// A memory read that has been started but not collected yet.
+///
+/// Returned by [`Connection::mem_read_start`](struct.Connection.html#method.mem_read_start) and
+/// passed to [`Connection::try_mem_read`](struct.Connection.html#method.try_mem_read) to check
+/// whether the data has arrived. Dropping the token abandons the read. Unlike blocking reads,
+/// a chunk NTR answers with no data isn't retried; it fails with `Error::EmptyResponse`.
+pub struct ReadToken {
+    reads: Vec<PendingRead>,
+    received: usize,
+    data: Vec<u8>,
+    collected: bool,
+}
+
+impl ReadToken {
+    pub(crate) fn new(reads: Vec<PendingRead>) -> Self {
+        let size = reads.iter().map(|read| read.size as usize).sum();
+        ReadToken {
+            reads,
+            received: 0,
+            data: Vec::with_capacity(size),
+            collected: false,
+        }
+    }
+
+    /// Collects whichever chunks have arrived, returning the data once all of them have.
+    pub(crate) fn poll(&mut self) -> Result<Option<Vec<u8>>> {
+        if self.collected {
+            return Err(Error::Io(io::Error::new(io::ErrorKind::InvalidInput,
+                                                "read was already collected")));
+        }
+        while self.received < self.reads.len() {
+            match self.reads[self.received].try_wait()? {
+                Some(chunk) => self.data.extend_from_slice(&chunk),
+                None => return Ok(None),
+            }
+            self.received += 1;
+        }
+        self.collected = true;
+        Ok(Some(mem::take(&mut self.data)))
+    }
+}
+
+impl fmt::Debug for ReadToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ReadToken")
+            .field("chunks", &self.reads.len())
+            .field("received", &self.received)
+            .finish()
+    }
+}
+
+/// Rejects an address of zero, a size of zero, and ranges running past the end of the address
+/// space.
+pub(crate) fn validate_range(addr: u32, size: u32) -> Result<()> {
+    let msg = if addr == 0 {
+        "address is zero"
+    } else if size == 0 {
+        "size is zero"
+    } else if addr.checked_add(size - 1).is_none() {
+        "range runs past the end of the address space"
+    } else {
+        return Ok(());
+    };
+    Err(Error::Io(io::Error::new(io::ErrorKind::InvalidInput, msg)))
+}
+
+/// The state shared between a `Connection` and its background threads.
+///
+/// Cloning it is cheap, so helpers that read memory from their own thread, like watches, take
+/// a clone instead of borrowing the connection.
+#[derive(Clone, Debug)]
+pub(crate) struct Link {
+    pub(crate) ntr_sender: Arc<Mutex<NtrSender>>,
+    pub(crate) connected: Arc<AtomicBool>,
+    pub(crate) pending: PendingResponses,
+    pub(crate) validate_addresses: bool,
+    pub(crate) chunk_len: u32,
+}
+
+impl Link {
+    /// Reads `size` bytes at `addr`, waiting up to `timeout` for each chunk.
+    pub(crate) fn read(&self,
+                       addr: u32,
+                       size: u32,
+                       pid: Pid,
+                       timeout: Duration)
+                       -> Result<Vec<u8>> {
+        let reads = {
+            let mut ntr_sender = self.ntr_sender.lock().unwrap();
+            self.send_mem_read(&mut ntr_sender, addr, size, pid)?
+        };
+        self.wait_mem_read(reads, timeout)
+    }
+
+    /// Sends the requests for a memory read, split into chunks of at most `chunk_len` bytes.
+    pub(crate) fn send_mem_read(&self,
+                                ntr_sender: &mut NtrSender,
+                                addr: u32,
+                                size: u32,
+                                pid: Pid)
+                                -> Result<Vec<PendingRead>> {
+        self.validate(addr, size)?;
+        let mut reads = Vec::with_capacity((size / self.chunk_len + 1) as usize);
+        let mut offset = 0;
+        while offset < size {
+            let chunk_len = cmp::min(self.chunk_len, size - offset);
+            reads.push(self.send_mem_read_chunk(ntr_sender,
+                                                addr.wrapping_add(offset),
+                                                chunk_len,
+                                                pid)?);
+            offset += chunk_len;
+        }
+        Ok(reads)
+    }
+
+    /// Sends a single memory read request and registers it to receive the response.
+    fn send_mem_read_chunk(&self,
+                           ntr_sender: &mut NtrSender,
+                           addr: u32,
+                           size: u32,
+                           pid: Pid)
+                           -> Result<PendingRead> {
+        let response = self.register_response(ntr_sender)?;
+        ntr_sender.send_mem_read_packet(addr, size, pid.0)?;
+        Ok(PendingRead {
+               response,
+               addr,
+               size,
+               pid,
+           })
+    }
+
+    /// Registers to receive the response to the next packet `ntr_sender` sends.
+    pub(crate) fn register_response(&self, ntr_sender: &NtrSender) -> Result<PendingResponse> {
+        let (tx, rx) = mpsc::channel();
+        let seq = ntr_sender.current_seq();
+        self.pending.lock().unwrap().insert(seq, (tx, Instant::now()));
+        let response = PendingResponse {
+            seq,
+            rx,
+            pending: self.pending.clone(),
+        };
+
+        // checked after registering, so a receiver thread that exits concurrently has either
+        // cleared our entry or already marked the connection as closed
+        self.check_connected()?;
+        Ok(response)
+    }
+
+    /// Waits for the responses to a read sent with `send_mem_read` and reassembles them.
+    pub(crate) fn wait_mem_read(&self,
+                                mut reads: Vec<PendingRead>,
+                                timeout: Duration)
+                                -> Result<Vec<u8>> {
+        if reads.len() == 1 {
+            return self.wait_mem_read_chunk(reads.pop().unwrap(), timeout);
+        }
+
+        let mut data = Vec::with_capacity(reads.iter().map(|read| read.size as usize).sum());
+        for read in reads {
+            data.extend_from_slice(&self.wait_mem_read_chunk(read, timeout)?);
+        }
+        Ok(data)
+    }
+
+    /// Waits for the response to a single chunk of a read, asking again once if NTR answers
+    /// with no data.
+    fn wait_mem_read_chunk(&self, read: PendingRead, timeout: Duration) -> Result<Vec<u8>> {
+        let (addr, size, pid) = (read.addr, read.size, read.pid);
+        match read.wait(timeout) {
+            // NTR occasionally answers a valid read with nothing, mostly right after attaching;
+            // the same request usually succeeds the second time
+            Err(Error::EmptyResponse) => {
+                let retry = {
+                    let mut ntr_sender = self.ntr_sender.lock().unwrap();
+                    self.send_mem_read_chunk(&mut ntr_sender, addr, size, pid)?
+                };
+                retry.wait(timeout)
+            }
+            result => result,
+        }
+    }
+
+    /// Sends the packets for a memory write, split into chunks of at most `chunk_len` bytes.
+    pub(crate) fn send_mem_write(&self,
+                                 ntr_sender: &mut NtrSender,
+                                 addr: u32,
+                                 data: &[u8],
+                                 pid: Pid)
+                                 -> io::Result<()> {
+        let mut written = 0;
+        for chunk in data.chunks(self.chunk_len as usize) {
+            ntr_sender.send_mem_write_packet(addr.wrapping_add(written as u32), pid.0, chunk)?;
+            written += chunk.len();
+        }
+        Ok(())
+    }
+
+    /// Checks a write of `data` to `addr`: the data has to fit in the address space, and the
+    /// range is checked like `validate` does.
+    pub(crate) fn validate_write(&self, addr: u32, data: &[u8]) -> Result<()> {
+        if data.len() > u32::MAX as usize {
+            return Err(Error::Io(io::Error::new(io::ErrorKind::InvalidInput,
+                                                "data is larger than the address space")));
+        }
+        self.validate(addr, data.len() as u32)
+    }
+
+    /// Checks a read or write with `validate_range`, unless validation is turned off.
+    pub(crate) fn validate(&self, addr: u32, size: u32) -> Result<()> {
+        if self.validate_addresses {
+            validate_range(addr, size)
+        } else {
+            Ok(())
+        }
+    }
+
+    pub(crate) fn check_connected(&self) -> Result<()> {
+        if self.connected.load(Ordering::SeqCst) {
+            Ok(())
+        } else {
+            Err(Error::Disconnected)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_invalid_input(result: Result<()>) -> bool {
+        match result {
+            Err(Error::Io(ref err)) => err.kind() == io::ErrorKind::InvalidInput,
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn range_ending_at_top_of_address_space() {
+        assert!(validate_range(0xFFFF_FFFF, 1).is_ok());
+        assert!(validate_range(0xFFFF_FFFC, 4).is_ok());
+        assert!(validate_range(1, 0xFFFF_FFFF).is_ok());
+    }
+
+    #[test]
+    fn range_past_top_of_address_space() {
+        assert!(is_invalid_input(validate_range(0xFFFF_FFFF, 2)));
+        assert!(is_invalid_input(validate_range(0xFFFF_FFFC, 5)));
+        assert!(is_invalid_input(validate_range(2, 0xFFFF_FFFF)));
+    }
+
+    #[test]
+    fn zero_address_or_size() {
+        assert!(is_invalid_input(validate_range(0, 4)));
+        assert!(is_invalid_input(validate_range(0x100000, 0)));
+        assert!(is_invalid_input(validate_range(0, 0)));
+    }
+
+    #[test]
+    fn response_of_requested_size() {
+        assert_eq!(check_size(4, vec![1, 2, 3, 4]).unwrap(), vec![1, 2, 3, 4]);
+        assert_eq!(check_size(0, Vec::new()).unwrap(), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn empty_response() {
+        assert!(matches!(check_size(4, Vec::new()), Err(Error::EmptyResponse)));
+    }
+
+    #[test]
+    fn response_of_wrong_size() {
+        assert!(matches!(check_size(4, vec![1, 2]), Err(Error::Parse(_))));
+        assert!(matches!(check_size(4, vec![0; 5]), Err(Error::Parse(_))));
+        assert!(matches!(check_size(0, vec![1]), Err(Error::Parse(_))));
+    }
+}
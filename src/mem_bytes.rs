@@ -0,0 +1,146 @@
+use byteorder::{ByteOrder, LittleEndian};
+
+/// A fixed-size value that can be decoded from the little-endian bytes of 3DS memory.
+///
+/// This is implemented for all the primitive numeric types, and can be implemented for your own
+/// fixed-layout types to read them with [`Connection::read`](struct.Connection.html#method.read).
+///
+/// # Examples
+///
+/// ```
+/// extern crate byteorder;
+/// # extern crate ntr;
+///
+/// use byteorder::{ByteOrder, LittleEndian};
+/// use ntr::FromMemBytes;
+///
+/// struct Position {
+///     x: f32,
+///     y: f32,
+/// }
+///
+/// impl FromMemBytes for Position {
+///     const SIZE: usize = 8;
+///
+///     fn from_le_bytes(bytes: &[u8]) -> Self {
+///         Position {
+///             x: LittleEndian::read_f32(&bytes[0..4]),
+///             y: LittleEndian::read_f32(&bytes[4..8]),
+///         }
+///     }
+/// }
+/// # fn main() {}
+/// ```
+pub trait FromMemBytes: Sized {
+    /// The number of bytes the value occupies in memory.
+    const SIZE: usize;
+
+    /// Decodes a value from `bytes`, which is exactly `SIZE` bytes long.
+    fn from_le_bytes(bytes: &[u8]) -> Self;
+
+    /// Decodes a value from big-endian `bytes`, which is exactly `SIZE` bytes long.
+    ///
+    /// The default implementation reverses the bytes and passes them to `from_le_bytes`, which
+    /// is correct for single numbers. Types made of several fields should override it.
+    fn from_be_bytes(bytes: &[u8]) -> Self {
+        let reversed: Vec<u8> = bytes.iter().rev().cloned().collect();
+        Self::from_le_bytes(&reversed)
+    }
+}
+
+impl FromMemBytes for u8 {
+    const SIZE: usize = 1;
+
+    fn from_le_bytes(bytes: &[u8]) -> Self {
+        bytes[0]
+    }
+}
+
+impl FromMemBytes for i8 {
+    const SIZE: usize = 1;
+
+    fn from_le_bytes(bytes: &[u8]) -> Self {
+        bytes[0] as i8
+    }
+}
+
+macro_rules! impl_from_mem_bytes {
+    ($($ty:ty, $size:expr, $read:ident;)*) => {
+        $(
+            impl FromMemBytes for $ty {
+                const SIZE: usize = $size;
+
+                fn from_le_bytes(bytes: &[u8]) -> Self {
+                    LittleEndian::$read(bytes)
+                }
+            }
+        )*
+    }
+}
+
+impl_from_mem_bytes! {
+    u16, 2, read_u16;
+    u32, 4, read_u32;
+    u64, 8, read_u64;
+    i16, 2, read_i16;
+    i32, 4, read_i32;
+    i64, 8, read_i64;
+    f32, 4, read_f32;
+    f64, 8, read_f64;
+}
+
+/// A value that can be encoded as the little-endian bytes it occupies in 3DS memory.
+///
+/// This is implemented for all the primitive numeric types, and is the counterpart to
+/// [`FromMemBytes`](trait.FromMemBytes.html) for writes.
+pub trait ToMemBytes {
+    /// Encodes the value as little-endian bytes.
+    fn to_le_bytes(&self) -> Vec<u8>;
+
+    /// Encodes the value as big-endian bytes.
+    ///
+    /// The default implementation reverses the output of `to_le_bytes`, which is correct for
+    /// single numbers. Types made of several fields should override it.
+    fn to_be_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.to_le_bytes();
+        bytes.reverse();
+        bytes
+    }
+}
+
+impl ToMemBytes for u8 {
+    fn to_le_bytes(&self) -> Vec<u8> {
+        vec![*self]
+    }
+}
+
+impl ToMemBytes for i8 {
+    fn to_le_bytes(&self) -> Vec<u8> {
+        vec![*self as u8]
+    }
+}
+
+macro_rules! impl_to_mem_bytes {
+    ($($ty:ty, $size:expr, $write:ident;)*) => {
+        $(
+            impl ToMemBytes for $ty {
+                fn to_le_bytes(&self) -> Vec<u8> {
+                    let mut buf = vec![0u8; $size];
+                    LittleEndian::$write(&mut buf, *self);
+                    buf
+                }
+            }
+        )*
+    }
+}
+
+impl_to_mem_bytes! {
+    u16, 2, write_u16;
+    u32, 4, write_u32;
+    u64, 8, write_u64;
+    i16, 2, write_i16;
+    i32, 4, write_i32;
+    i64, 8, write_i64;
+    f32, 4, write_f32;
+    f64, 8, write_f64;
+}
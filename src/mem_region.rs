@@ -0,0 +1,123 @@
+use crate::process::parse_hex;
+use regex::Regex;
+use std::sync::OnceLock;
+
+/// A mapped region of a process's memory, as reported by NTR's memory layout.
+///
+/// NTR only lists which ranges are mapped, not their permissions, so a region may still be
+/// read-only or code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MemRegion {
+    /// The address the region starts at.
+    pub base: u32,
+    /// The length of the region in bytes.
+    pub size: u32,
+}
+
+impl MemRegion {
+    /// Returns the address just past the end of the region, which is `0x100000000` for a
+    /// region ending at the top of the address space.
+    pub fn end(&self) -> u64 {
+        self.base as u64 + self.size as u64
+    }
+
+    /// Returns `true` if `addr` lies inside the region.
+    pub fn contains(&self, addr: u32) -> bool {
+        addr >= self.base && (addr as u64) < self.end()
+    }
+}
+
+/// Parses every region out of the text NTR sends in response to a memory layout request.
+///
+/// [`Connection::memory_regions`](struct.Connection.html#method.memory_regions) uses this on the
+/// live response; like [`parse_process_list`](fn.parse_process_list.html), it's public so
+/// captured output can be parsed without a 3DS. Lines that aren't regions are skipped.
+///
+/// # Examples
+///
+/// ```
+/// use ntr::{parse_memory_layout, MemRegion};
+///
+/// let text = "valid memregions:\n\
+///             00100000 - 0037bfff , size: 0027c000\n\
+///             end of memlayout.\n";
+/// assert_eq!(parse_memory_layout(text),
+///            vec![MemRegion { base: 0x100000, size: 0x27c000 }]);
+/// ```
+pub fn parse_memory_layout(text: &str) -> Vec<MemRegion> {
+    static RE: OnceLock<Regex> = OnceLock::new();
+
+    // the end address is inclusive and redundant with the size, so only the size is used; the
+    // word boundaries keep a longer number from being read as its last eight digits
+    let re = RE.get_or_init(|| {
+        Regex::new(concat!(r"\b([0-9a-fA-F]{1,8})\s*-\s*[0-9a-fA-F]{1,8}\s*,",
+                           r"\s*size:\s*([0-9a-fA-F]{1,8})\b"))
+            .unwrap()
+    });
+    re.captures_iter(text)
+        .map(|cap| {
+                 MemRegion {
+                     base: parse_hex(&cap[1]) as u32,
+                     size: parse_hex(&cap[2]) as u32,
+                 }
+             })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LAYOUT: &str = "valid memregions:\n\
+                          00100000 - 0037bfff , size: 0027c000\n\
+                          08000000 - 0834ffff , size: 00350000\n\
+                          end of memlayout.\n";
+
+    #[test]
+    fn captured_layout() {
+        assert_eq!(parse_memory_layout(LAYOUT),
+                   vec![MemRegion { base: 0x100000, size: 0x27c000 },
+                        MemRegion { base: 0x8000000, size: 0x350000 }]);
+    }
+
+    #[test]
+    fn missing_trailer() {
+        let text = LAYOUT.trim_end_matches("end of memlayout.\n");
+        assert_eq!(parse_memory_layout(text).len(), 2);
+    }
+
+    #[test]
+    fn truncated_line_is_skipped() {
+        let text = "00100000 - 0037bfff , size: 0027c000\n08000000 - 0834ffff , siz";
+        assert_eq!(parse_memory_layout(text),
+                   vec![MemRegion { base: 0x100000, size: 0x27c000 }]);
+        assert_eq!(parse_memory_layout("08000000 - 0834ffff , size: "), vec![]);
+    }
+
+    #[test]
+    fn malformed_lines_are_skipped() {
+        let text = "[0] attached to process\n\
+                    00100000 0037bfff 0027c000\n\
+                    123456789 - 0037bfff , size: 0027c000\n\
+                    00100000 - 0037bfff , size: 10027c000\n\
+                    zz100000 - 0037bfff , size: 0027c000\n";
+        assert_eq!(parse_memory_layout(text), vec![]);
+    }
+
+    #[test]
+    fn uppercase_and_spacing() {
+        let text = "1FF80000-1FF80FFF,size:00001000\n1ff81000  -  1ff81fff ,  size:  1000\n";
+        assert_eq!(parse_memory_layout(text),
+                   vec![MemRegion { base: 0x1ff80000, size: 0x1000 },
+                        MemRegion { base: 0x1ff81000, size: 0x1000 }]);
+    }
+
+    #[test]
+    fn region_at_top_of_address_space() {
+        let regions = parse_memory_layout("fff00000 - ffffffff , size: 00100000\n");
+        assert_eq!(regions[0].end(), 0x1_0000_0000);
+        assert!(regions[0].contains(0xffff_ffff));
+        assert!(!regions[0].contains(0xffef_ffff));
+    }
+}
@@ -1,20 +1,35 @@
-use byteorder::{ByteOrder, LittleEndian};
 use std::io;
 use std::io::prelude::*;
 use std::net::TcpStream;
+use std::sync::Arc;
+use std::time::Instant;
+use crate::packet::{Direction, PacketEvent, PacketLogger, encode_header};
+use crate::stats::StatsCounters;
 
 #[derive(Debug)]
 pub struct NtrSender {
     tcp_stream: TcpStream,
     current_seq: u32,
     is_heartbeat_sendable: bool,
+    last_activity: Instant,
+    heartbeat_sent: Option<Instant>,
+    logger: PacketLogger,
+    stats: Arc<StatsCounters>,
 }
 impl NtrSender {
-    pub fn new(tcp_stream: TcpStream) -> Self {
+    pub fn new(tcp_stream: TcpStream,
+               initial_seq: u32,
+               logger: PacketLogger,
+               stats: Arc<StatsCounters>)
+               -> Self {
         NtrSender {
-            tcp_stream: tcp_stream,
-            current_seq: 1000,
+            tcp_stream,
+            current_seq: initial_seq,
             is_heartbeat_sendable: true,
+            last_activity: Instant::now(),
+            heartbeat_sent: None,
+            logger,
+            stats,
         }
     }
 
@@ -26,47 +41,86 @@ impl NtrSender {
         self.is_heartbeat_sendable = b;
     }
 
-    pub fn send_mem_read_packet(&mut self, addr: u32, size: u32, pid: u32) -> io::Result<usize> {
-        self.send_empty_packet(9, pid, addr, size)
+    pub fn last_activity(&self) -> Instant {
+        self.last_activity
     }
 
-    pub fn send_mem_write_packet(&mut self, addr: u32, pid: u32, buf: &[u8]) -> io::Result<usize> {
+    pub fn current_seq(&self) -> u32 {
+        self.current_seq
+    }
+
+    pub fn send_mem_read_packet(&mut self, addr: u32, size: u32, pid: u32) -> io::Result<()> {
+        self.send_empty_packet(9, pid, addr, size)?;
+        self.stats.record_read_request();
+        Ok(())
+    }
+
+    pub fn send_mem_write_packet(&mut self, addr: u32, pid: u32, buf: &[u8]) -> io::Result<()> {
         let args = &mut [0u32; 16];
         args[0] = pid;
         args[1] = addr;
         args[2] = buf.len() as u32;
         self.send_packet(1, 10, args, args[2])?;
-        self.tcp_stream.write(buf)
+        self.tcp_stream.write_all(buf)?;
+        self.stats.record_write(buf.len());
+        Ok(())
     }
 
-    pub fn send_heartbeat_packet(&mut self) -> io::Result<usize> {
+    pub fn send_raw_packet(&mut self,
+                           packet_type: u32,
+                           cmd: u32,
+                           args: &[u32; 16],
+                           data: &[u8])
+                           -> io::Result<()> {
+        self.send_packet(packet_type, cmd, args, data.len() as u32)?;
+        self.tcp_stream.write_all(data)
+    }
+
+    pub fn send_heartbeat_packet(&mut self) -> io::Result<()> {
+        self.heartbeat_sent = Some(Instant::now());
         self.send_packet(0, 0, &[0u32; 16], 0)
     }
 
-    pub fn send_list_process_packet(&mut self) -> io::Result<usize> {
+    pub fn take_heartbeat_sent(&mut self) -> Option<Instant> {
+        self.heartbeat_sent.take()
+    }
+
+    pub fn send_hello_packet(&mut self) -> io::Result<()> {
+        self.send_empty_packet(3, 0, 0, 0)
+    }
+
+    pub fn send_reload_packet(&mut self) -> io::Result<()> {
+        self.send_empty_packet(4, 0, 0, 0)
+    }
+
+    pub fn send_list_process_packet(&mut self) -> io::Result<()> {
         self.send_empty_packet(5, 0, 0, 0)
     }
 
+    pub fn send_memlayout_packet(&mut self, pid: u32) -> io::Result<()> {
+        self.send_empty_packet(8, pid, 0, 0)
+    }
+
     fn send_packet(&mut self,
                    packet_type: u32,
                    cmd: u32,
                    args: &[u32; 16],
                    data_len: u32)
-                   -> io::Result<usize> {
-        let mut buf = [0u8; 84];
-
-        LittleEndian::write_u32(&mut buf[0..4], 0x12345678);
-        LittleEndian::write_u32(&mut buf[4..8], self.current_seq);
-        LittleEndian::write_u32(&mut buf[8..12], packet_type);
-        LittleEndian::write_u32(&mut buf[12..16], cmd);
-        for (mut i, val) in args.iter().enumerate() {
-            i = 4 * i + 16;
-            LittleEndian::write_u32(&mut buf[i..(i + 4)], *val);
-        }
-        LittleEndian::write_u32(&mut buf[80..84], data_len);
+                   -> io::Result<()> {
+        let buf = encode_header(self.current_seq, packet_type, cmd, args, data_len);
 
-        self.current_seq += 1000;
-        self.tcp_stream.write(&buf)
+        self.logger.call(&PacketEvent {
+                              direction: Direction::Sent,
+                              seq: self.current_seq,
+                              packet_type,
+                              cmd,
+                              data_len,
+                          });
+        self.current_seq = self.current_seq.wrapping_add(1000);
+        if cmd != 0 {
+            self.last_activity = Instant::now();
+        }
+        self.tcp_stream.write_all(&buf)
     }
 
     fn send_empty_packet(&mut self,
@@ -74,7 +128,7 @@ impl NtrSender {
                          arg0: u32,
                          arg1: u32,
                          arg2: u32)
-                         -> io::Result<usize> {
+                         -> io::Result<()> {
         let mut args = [0u32; 16];
         args[0] = arg0;
         args[1] = arg1;
@@ -0,0 +1,142 @@
+use byteorder::{ByteOrder, LittleEndian};
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+/// The magic number every NTR packet header starts with.
+pub(crate) const MAGIC: u32 = 0x12345678;
+
+/// The length of an NTR packet header in bytes.
+pub(crate) const HEADER_LEN: usize = 84;
+
+/// A packet received from NTR.
+///
+/// Every NTR packet, in both directions, is an 84-byte header followed by `data_len` bytes of
+/// data. All header fields are little-endian `u32`s:
+///
+/// | Offset | Field |
+/// |--------|-------|
+/// | 0      | magic number, always `0x12345678` |
+/// | 4      | sequence number; replies carry the sequence number of the request |
+/// | 8      | packet type: 1 for requests carrying data, like memory writes, otherwise 0 |
+/// | 12     | command, e.g. 0 for heartbeat, 5 for process list, 9 for memory read |
+/// | 16     | 16 command arguments |
+/// | 80     | length of the data following the header |
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Packet {
+    /// The sequence number.
+    pub seq: u32,
+    /// The packet type.
+    pub packet_type: u32,
+    /// The command.
+    pub cmd: u32,
+    /// The command arguments.
+    pub args: [u32; 16],
+    /// The data following the header.
+    pub data: Vec<u8>,
+}
+
+impl Packet {
+    /// Returns `true` if `buf` starts with the magic number, which is the only sign that the
+    /// other end is speaking NTR's protocol at all.
+    pub(crate) fn has_magic(buf: &[u8; HEADER_LEN]) -> bool {
+        LittleEndian::read_u32(&buf[0..4]) == MAGIC
+    }
+
+    /// Parses a packet header, returning a packet with empty data and the length of the data
+    /// that follows the header.
+    pub(crate) fn from_header(buf: &[u8; HEADER_LEN]) -> (Packet, usize) {
+        let mut args = [0u32; 16];
+        LittleEndian::read_u32_into(&buf[16..80], &mut args);
+        let packet = Packet {
+            seq: LittleEndian::read_u32(&buf[4..8]),
+            packet_type: LittleEndian::read_u32(&buf[8..12]),
+            cmd: LittleEndian::read_u32(&buf[12..16]),
+            args,
+            data: Vec::new(),
+        };
+        (packet, LittleEndian::read_u32(&buf[80..84]) as usize)
+    }
+}
+
+/// Builds the header of a packet to send.
+pub(crate) fn encode_header(seq: u32,
+                            packet_type: u32,
+                            cmd: u32,
+                            args: &[u32; 16],
+                            data_len: u32)
+                            -> [u8; HEADER_LEN] {
+    let mut buf = [0u8; HEADER_LEN];
+    LittleEndian::write_u32(&mut buf[0..4], MAGIC);
+    LittleEndian::write_u32(&mut buf[4..8], seq);
+    LittleEndian::write_u32(&mut buf[8..12], packet_type);
+    LittleEndian::write_u32(&mut buf[12..16], cmd);
+    LittleEndian::write_u32_into(args, &mut buf[16..80]);
+    LittleEndian::write_u32(&mut buf[80..84], data_len);
+    buf
+}
+
+/// Whether a logged packet was sent or received.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Direction {
+    /// The packet was sent to NTR.
+    Sent,
+    /// The packet was received from NTR.
+    Received,
+}
+
+/// The header of a packet sent or received, as passed to a packet logger.
+///
+/// See [`Connection::set_packet_logger`](struct.Connection.html#method.set_packet_logger).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct PacketEvent {
+    /// Whether the packet was sent or received.
+    pub direction: Direction,
+    /// The sequence number.
+    pub seq: u32,
+    /// The packet type.
+    pub packet_type: u32,
+    /// The command.
+    pub cmd: u32,
+    /// The length of the data following the header.
+    pub data_len: u32,
+}
+
+/// An optional callback that can be replaced at any time, shared between a connection and its
+/// threads.
+pub(crate) struct Callback<T>(Arc<RwLock<Option<CallbackFn<T>>>>);
+
+pub(crate) type CallbackFn<T> = Box<dyn Fn(&T) + Send + Sync>;
+
+/// The callback packets are logged to, shared by the sending and receiving sides.
+pub(crate) type PacketLogger = Callback<PacketEvent>;
+
+impl<T> Callback<T> {
+    pub(crate) fn set(&self, callback: Option<CallbackFn<T>>) {
+        *self.0.write().unwrap() = callback;
+    }
+
+    pub(crate) fn call(&self, arg: &T) {
+        if let Some(ref callback) = *self.0.read().unwrap() {
+            callback(arg);
+        }
+    }
+}
+
+impl<T> Clone for Callback<T> {
+    fn clone(&self) -> Self {
+        Callback(self.0.clone())
+    }
+}
+
+impl<T> Default for Callback<T> {
+    fn default() -> Self {
+        Callback(Arc::new(RwLock::new(None)))
+    }
+}
+
+impl<T> fmt::Debug for Callback<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let is_set = self.0.read().map(|callback| callback.is_some()).unwrap_or(false);
+        f.debug_tuple("Callback").field(&is_set).finish()
+    }
+}
@@ -0,0 +1,239 @@
+use regex::Regex;
+use std::fmt;
+use std::mem;
+use std::sync::OnceLock;
+
+/// A process id.
+///
+/// Memory reads and writes take the process id as a `Pid` rather than a bare `u32`, so it can't
+/// be swapped with an address by accident. Get one from `Connection::get_pid`, or wrap a known
+/// id with `Pid(id)`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Pid(pub u32);
+
+impl From<u32> for Pid {
+    fn from(pid: u32) -> Self {
+        Pid(pid)
+    }
+}
+
+impl From<Pid> for u32 {
+    fn from(pid: Pid) -> Self {
+        pid.0
+    }
+}
+
+impl fmt::Display for Pid {
+    /// Formats the id in hex the way NTR's process list does, e.g. `0x0000002a`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:#010x}", self.0)
+    }
+}
+
+/// The text NTR ends its process list with.
+pub(crate) const PROCESS_LIST_END: &str = "end of process list.";
+
+/// The text NTR ends a process's memory layout with.
+pub(crate) const MEMORY_LAYOUT_END: &str = "end of memlayout.";
+
+/// The most text buffered while waiting for an end marker.
+const MAX_OUTPUT_TEXT_LEN: usize = 0x10_0000;
+
+/// Collects NTR's text output until a whole response, like the process list, has arrived.
+///
+/// NTR may split a long response over several packets, so the end marker can show up well
+/// after the first lines.
+#[derive(Debug)]
+pub(crate) struct OutputBuffer {
+    end_marker: &'static str,
+    text: String,
+}
+
+impl OutputBuffer {
+    /// Creates a buffer for the response ending with `end_marker`.
+    pub(crate) fn new(end_marker: &'static str) -> Self {
+        OutputBuffer {
+            end_marker,
+            text: String::new(),
+        }
+    }
+
+    /// Adds text NTR sent, returning the response once its end marker has arrived.
+    pub(crate) fn push(&mut self, text: &str) -> Option<String> {
+        self.text.push_str(text);
+        if let Some(i) = self.text.find(self.end_marker) {
+            let rest = self.text.split_off(i + self.end_marker.len());
+            Some(mem::replace(&mut self.text, rest))
+        } else {
+            if self.text.len() > MAX_OUTPUT_TEXT_LEN {
+                // nobody asked for this response; don't hold on to log output
+                self.text.clear();
+            }
+            None
+        }
+    }
+}
+
+/// A process running on the 3DS, as reported by NTR's process list.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ProcessInfo {
+    /// The process id.
+    pub pid: Pid,
+    /// The process name.
+    pub pname: String,
+    /// The title id of the process.
+    pub tid: u64,
+}
+
+/// The high half of the title id shared by games and other applications, as opposed to system
+/// modules, applets and the like.
+const APPLICATION_TID_HIGH: u32 = 0x00040000;
+
+/// The high half of the title id of demo versions of games.
+const DEMO_TID_HIGH: u32 = 0x00040002;
+
+impl ProcessInfo {
+    /// Returns `true` if the title id is that of an application, like a game or its demo,
+    /// rather than a system title.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use ntr::{Pid, ProcessInfo};
+    ///
+    /// let game = ProcessInfo { pid: Pid(0x29), pname: "mhgen".into(), tid: 0x0004000000155400 };
+    /// let fs = ProcessInfo { pid: Pid(0), pname: "fs".into(), tid: 0x0004013000001102 };
+    /// assert!(game.is_application());
+    /// assert!(!fs.is_application());
+    /// ```
+    pub fn is_application(&self) -> bool {
+        let high = (self.tid >> 32) as u32;
+        high == APPLICATION_TID_HIGH || high == DEMO_TID_HIGH
+    }
+}
+
+/// Parses every process entry out of the text NTR sends in response to a process list request.
+///
+/// [`Connection::list_processes`](struct.Connection.html#method.list_processes) uses this on the
+/// live response; it's public so captured output can be parsed without a 3DS. Lines that aren't
+/// process entries are skipped.
+///
+/// # Examples
+///
+/// ```
+/// use ntr::{parse_process_list, Pid};
+///
+/// let text = "pid: 0x00000029, pname:    mhgen, tid: 0004000000155400, kpobj: fff7c7a0\n\
+///             end of process list.\n";
+/// let processes = parse_process_list(text);
+/// assert_eq!(processes.len(), 1);
+/// assert_eq!(processes[0].pid, Pid(0x29));
+/// assert_eq!(processes[0].pname, "mhgen");
+/// assert_eq!(processes[0].tid, 0x0004000000155400);
+/// ```
+pub fn parse_process_list(text: &str) -> Vec<ProcessInfo> {
+    static RE: OnceLock<Regex> = OnceLock::new();
+
+    // NTR zero-pads the ids, but the widths aren't relied on; they're compared as numbers
+    let re = RE.get_or_init(|| {
+        Regex::new(r"pid: 0x([0-9a-fA-F]{1,8}), pname:\s*([^,]*), tid: ([0-9a-fA-F]{1,16})")
+            .unwrap()
+    });
+    re.captures_iter(text)
+        .map(|cap| {
+                 ProcessInfo {
+                     pid: Pid(parse_hex(&cap[1]) as u32),
+                     pname: cap[2].trim().to_owned(),
+                     tid: parse_hex(&cap[3]),
+                 }
+             })
+        .collect()
+}
+
+/// Parses a number out of NTR's text output that a regex has already matched as 1 to 16 hex
+/// digits.
+///
+/// The regexes only allow 8 digits for 32-bit values, so truncating those to `u32` loses
+/// nothing.
+pub(crate) fn parse_hex(digits: &str) -> u64 {
+    u64::from_str_radix(digits, 16).expect("regex matched something that isn't a hex number")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FS: &str = "pid: 0x00000000, pname:      fs, tid: 0004013000001102, kpobj: fff00000\n";
+    const GAME: &str = "pid: 0x0000002a, pname:    mhgen, tid: 0004000000187000, kpobj: fff00001\n";
+
+    #[test]
+    fn list_in_one_packet() {
+        let mut buffer = OutputBuffer::new(PROCESS_LIST_END);
+        let text = format!("{}{}end of process list.\n", FS, GAME);
+        let list = buffer.push(&text).unwrap();
+        assert_eq!(parse_process_list(&list).len(), 2);
+    }
+
+    #[test]
+    fn list_split_across_packets() {
+        let mut buffer = OutputBuffer::new(PROCESS_LIST_END);
+        assert_eq!(buffer.push(FS), None);
+        assert_eq!(buffer.push(&GAME[..20]), None);
+        assert_eq!(buffer.push(&format!("{}end of proc", &GAME[20..])), None);
+        let list = buffer.push("ess list.\n").unwrap();
+        let processes = parse_process_list(&list);
+        assert_eq!(processes.len(), 2);
+        assert_eq!(processes[1].pid, Pid(0x2a));
+        assert_eq!(processes[1].tid, 0x0004000000187000);
+    }
+
+    #[test]
+    fn text_after_end_marker_starts_next_response() {
+        let mut buffer = OutputBuffer::new(PROCESS_LIST_END);
+        let text = format!("{}end of process list.\n{}", FS, GAME);
+        let first = buffer.push(&text).unwrap();
+        assert_eq!(parse_process_list(&first).len(), 1);
+
+        // the leftover line and the newline after the marker carry over to the next list
+        let second = buffer.push("end of process list.\n").unwrap();
+        let processes = parse_process_list(&second);
+        assert_eq!(processes.len(), 1);
+        assert_eq!(processes[0].pname, "mhgen");
+        assert_eq!(buffer.push(""), None);
+    }
+
+    #[test]
+    fn log_output_before_list() {
+        let mut buffer = OutputBuffer::new(PROCESS_LIST_END);
+        assert_eq!(buffer.push("[0] hello\n"), None);
+        let list = buffer.push(&format!("{}end of process list.\n", FS)).unwrap();
+        assert!(list.starts_with("[0] hello\n"));
+        assert_eq!(parse_process_list(&list).len(), 1);
+    }
+
+    #[test]
+    fn output_past_cap_is_dropped() {
+        let mut buffer = OutputBuffer::new(PROCESS_LIST_END);
+        let log = "x".repeat(MAX_OUTPUT_TEXT_LEN / 4 + 1);
+        for _ in 0..4 {
+            assert_eq!(buffer.push(&log), None);
+        }
+        assert!(buffer.text.is_empty());
+
+        // a response that arrives afterwards is still collected whole
+        let list = buffer.push(&format!("{}end of process list.\n", GAME)).unwrap();
+        assert_eq!(list, format!("{}end of process list.", GAME));
+    }
+
+    #[test]
+    fn memory_layout_marker() {
+        let mut buffer = OutputBuffer::new(MEMORY_LAYOUT_END);
+        assert_eq!(buffer.push(&format!("{}end of process list.\n", FS)), None);
+        let layout = buffer.push("valid memregions:\n00100000 - 0013ffff , size: 00040000\n\
+                                  end of memlayout.\n")
+            .unwrap();
+        assert!(layout.ends_with("end of memlayout."));
+    }
+}
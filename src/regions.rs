@@ -0,0 +1,59 @@
+//! Addresses of the standard 3DS virtual memory layout.
+//!
+//! Every application sees the same layout, so these are the same for every game. The exact
+//! extent of each region depends on the game and the console model; use
+//! [`Connection::memory_regions`](../struct.Connection.html#method.memory_regions) to see what a
+//! process actually has mapped. NTR doesn't report a process's heap base, so
+//! [`HEAP_BASE`](constant.HEAP_BASE.html) is where to start looking for game data.
+//!
+//! # Examples
+//!
+//! Scanning the part of the heap window the process has mapped; NTR doesn't answer reads of
+//! unmapped memory.
+//!
+//! ```no_run
+//! use ntr::Connection;
+//! use ntr::regions::{HEAP_BASE, HEAP_END};
+//!
+//! # let connection: Connection = unimplemented!();
+//! # let pid = ntr::Pid(0);
+//! let mut candidates = Vec::new();
+//! for region in connection.memory_regions(pid).expect("io error") {
+//!     let start = region.base.max(HEAP_BASE);
+//!     let end = region.end().min(HEAP_END as u64) as u32;
+//!     if start < end {
+//!         candidates.extend(connection.scan_u32(start..end, 1500, pid).expect("io error"));
+//!     }
+//! }
+//! ```
+
+/// Where an application's code is loaded. Its read-only data and static data follow the code.
+pub const CODE_BASE: u32 = 0x0010_0000;
+
+/// The start of the application heap, where most game data lives.
+pub const HEAP_BASE: u32 = 0x0800_0000;
+
+/// The upper bound of the window the application heap is mapped in.
+///
+/// This is the end of the largest possible heap, not of the memory actually mapped; a heap
+/// usually ends well before this, with unmapped memory between it and the stack.
+pub const HEAP_END: u32 = 0x1000_0000;
+
+/// The top of the main thread's stack, which grows down from here.
+pub const STACK_TOP: u32 = 0x1000_0000;
+
+/// The start of the linear heap on system versions before 8.0.0.
+pub const LINEAR_HEAP_BASE_OLD: u32 = 0x1400_0000;
+
+/// The start of the linear heap, which on system version 8.0.0 and later is mapped here
+/// instead of at [`LINEAR_HEAP_BASE_OLD`](constant.LINEAR_HEAP_BASE_OLD.html).
+pub const LINEAR_HEAP_BASE: u32 = 0x3000_0000;
+
+/// The start of VRAM.
+pub const VRAM_BASE: u32 = 0x1F00_0000;
+
+/// The read-only page of system configuration, like the kernel version.
+pub const CONFIG_MEMORY: u32 = 0x1FF8_0000;
+
+/// The page the kernel shares with every process, holding things like the system time.
+pub const SHARED_PAGE: u32 = 0x1FF8_1000;
@@ -0,0 +1,116 @@
+use crate::link::Link;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use crate::{Error, FromMemBytes, Pid};
+
+/// The shortest interval a sampler reads at; shorter intervals, including zero, are raised to
+/// this.
+const MIN_INTERVAL: Duration = Duration::from_millis(1);
+
+/// A value read by a [`Sampler`](struct.Sampler.html), along with when it was read.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sample<T> {
+    /// When the read request was sent.
+    pub time: Instant,
+    /// The value read.
+    pub value: T,
+}
+
+/// A value in 3DS memory being read on a fixed schedule by a background thread.
+///
+/// Returned by [`Connection::sample`](struct.Connection.html#method.sample). Every sample is
+/// kept until it's taken with [`recv`](#method.recv), [`drain`](#method.drain) or
+/// [`stop`](#method.stop). Sampling ends when the sampler is stopped or dropped, or when the
+/// connection is lost.
+#[derive(Debug)]
+pub struct Sampler<T> {
+    samples_rx: Receiver<Sample<T>>,
+    stop_tx: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl<T> Sampler<T>
+    where T: FromMemBytes + Send + 'static
+{
+    pub(crate) fn spawn(link: Link,
+                        addr: u32,
+                        pid: Pid,
+                        interval: Duration,
+                        read_timeout: Duration)
+                        -> Self {
+        // a zero interval would never move the schedule forward
+        let interval = interval.max(MIN_INTERVAL);
+        let (samples_tx, samples_rx) = mpsc::channel();
+        let (stop_tx, stop_rx) = mpsc::channel::<()>();
+        let thread = thread::spawn(move || {
+            let mut next = Instant::now();
+            while link.connected.load(Ordering::SeqCst) {
+                let time = Instant::now();
+                match link.read(addr, T::SIZE as u32, pid, read_timeout) {
+                    Ok(data) => {
+                        let value = T::from_le_bytes(&data);
+                        if samples_tx.send(Sample { time, value }).is_err() {
+                            break;
+                        }
+                    }
+                    // a slow response just means this sample is missing
+                    Err(Error::Timeout) => {}
+                    Err(_) => break,
+                }
+
+                // samples are scheduled from the start time rather than from the last read, so
+                // slow reads don't make the schedule drift; missed slots are skipped
+                next += interval;
+                let now = Instant::now();
+                while next < now {
+                    next += interval;
+                }
+                if let Err(RecvTimeoutError::Disconnected) = stop_rx.recv_timeout(next - now) {
+                    break;
+                }
+            }
+        });
+
+        Sampler {
+            samples_rx,
+            stop_tx: Some(stop_tx),
+            thread: Some(thread),
+        }
+    }
+}
+
+impl<T> Sampler<T> {
+    /// Waits for the next sample.
+    ///
+    /// Returns `None` once sampling has ended and every sample has been taken.
+    pub fn recv(&self) -> Option<Sample<T>> {
+        self.samples_rx.recv().ok()
+    }
+
+    /// Takes every sample collected so far without waiting.
+    pub fn drain(&self) -> Vec<Sample<T>> {
+        self.samples_rx.try_iter().collect()
+    }
+
+    /// Stops sampling, waits for the background thread to exit, and returns the samples that
+    /// haven't been taken yet.
+    pub fn stop(mut self) -> Vec<Sample<T>> {
+        self.join();
+        self.drain()
+    }
+
+    fn join(&mut self) {
+        self.stop_tx.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl<T> Drop for Sampler<T> {
+    fn drop(&mut self) {
+        self.join();
+    }
+}
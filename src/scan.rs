@@ -0,0 +1,517 @@
+use byteorder::{ByteOrder, LittleEndian};
+use std::cmp;
+use std::ops::Range;
+use crate::{CHUNK_LEN, Connection, MemRegion, Pid, Result};
+
+/// How many candidate reads are pipelined per batch when refining a scan.
+const REFINE_BATCH_LEN: usize = 256;
+
+impl Connection {
+    /// Finds every address in `range` holding the `u32` `value`.
+    ///
+    /// Only 4-byte aligned addresses are checked, and a match must lie entirely inside `range`.
+    /// The range is read 64 KiB at a time. This is the first step of the usual cheat-search
+    /// workflow; the returned addresses can then be narrowed down as the value changes in game.
+    ///
+    /// NTR doesn't answer reads of unmapped memory, so the range must be mapped throughout or
+    /// the scan fails with `Error::Timeout`. [`scan_u32_all`](#method.scan_u32_all) scans only
+    /// the mapped regions instead.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use ntr::Connection;
+    ///
+    /// # let connection: Connection = unimplemented!();
+    /// # let pid = ntr::Pid(0);
+    /// let candidates = connection.scan_u32(0x8000000..0x8800000, 1500, pid).expect("io error");
+    /// ```
+    pub fn scan_u32(&self, range: Range<u32>, value: u32, pid: Pid) -> Result<Vec<u32>> {
+        // u64 so rounding and stepping can't overflow near the top of the address space
+        let start = (range.start as u64 + 3) & !3;
+        let end = range.end as u64 & !3;
+
+        let mut matches = Vec::new();
+        let mut chunk_addr = start;
+        while chunk_addr < end {
+            let chunk_len = cmp::min(CHUNK_LEN as u64, end - chunk_addr) as u32;
+            let chunk = self.mem_read(chunk_addr as u32, chunk_len, pid)?;
+            for (i, word) in chunk.chunks_exact(4).enumerate() {
+                if LittleEndian::read_u32(word) == value {
+                    matches.push(chunk_addr as u32 + 4 * i as u32);
+                }
+            }
+            chunk_addr += chunk_len as u64;
+        }
+        Ok(matches)
+    }
+
+    /// Finds every address in `range` where the bytes match `pattern`.
+    ///
+    /// Each `None` in `pattern` is a wildcard matching any byte, which makes it possible to
+    /// locate code or data signatures whose operands differ between game versions. A match must
+    /// lie entirely inside `range`. The range is read 64 KiB at a time, and matches straddling
+    /// two pieces are found too. An empty pattern matches nothing. As with
+    /// [`scan_u32`](#method.scan_u32), the range must be mapped throughout; see
+    /// [`search_pattern_all`](#method.search_pattern_all).
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use ntr::Connection;
+    ///
+    /// # let connection: Connection = unimplemented!();
+    /// # let pid = ntr::Pid(0);
+    /// // ldr r0, [pc, #??]; bx lr
+    /// let pattern = [None, Some(0x00), Some(0x9F), Some(0xE5), Some(0x1E), Some(0xFF), Some(0x2F),
+    ///                Some(0xE1)];
+    /// let matches = connection.search_pattern(0x100000..0x300000, &pattern, pid)
+    ///     .expect("io error");
+    /// ```
+    pub fn search_pattern(&self,
+                          range: Range<u32>,
+                          pattern: &[Option<u8>],
+                          pid: Pid)
+                          -> Result<Vec<u32>> {
+        if pattern.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut search = PatternSearch::new(pattern, range.start);
+        let mut chunk_addr = range.start as u64;
+        let end = range.end as u64;
+        while chunk_addr < end {
+            let chunk_len = cmp::min(CHUNK_LEN as u64, end - chunk_addr) as u32;
+            search.push(&self.mem_read(chunk_addr as u32, chunk_len, pid)?);
+            chunk_addr += chunk_len as u64;
+        }
+        Ok(search.matches)
+    }
+
+    /// Finds every address holding the `u32` `value` in any mapped region of the process's memory.
+    ///
+    /// The regions come from [`memory_regions`](#method.memory_regions), so there's no need to
+    /// know where the process keeps its data, and the gaps between regions are skipped. Each
+    /// region is scanned like [`scan_u32`](#method.scan_u32) does. This also scans code and
+    /// read-only data, since NTR doesn't report permissions.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use ntr::Connection;
+    ///
+    /// # let connection: Connection = unimplemented!();
+    /// # let pid = ntr::Pid(0);
+    /// let candidates = connection.scan_u32_all(1500, pid).expect("io error");
+    /// ```
+    pub fn scan_u32_all(&self, value: u32, pid: Pid) -> Result<Vec<u32>> {
+        let mut matches = Vec::new();
+        for region in self.memory_regions(pid)? {
+            matches.extend(self.scan_u32(region_range(&region), value, pid)?);
+        }
+        Ok(matches)
+    }
+
+    /// Finds every address where the bytes match `pattern` in any mapped region of the process's
+    /// memory.
+    ///
+    /// Each region from [`memory_regions`](#method.memory_regions) is searched like
+    /// [`search_pattern`](#method.search_pattern) does. A match straddling two adjacent regions
+    /// isn't found.
+    pub fn search_pattern_all(&self, pattern: &[Option<u8>], pid: Pid) -> Result<Vec<u32>> {
+        let mut matches = Vec::new();
+        for region in self.memory_regions(pid)? {
+            matches.extend(self.search_pattern(region_range(&region), pattern, pid)?);
+        }
+        Ok(matches)
+    }
+
+    /// Re-reads the `u32` at each candidate address and keeps the ones whose current value
+    /// satisfies `predicate`.
+    ///
+    /// This is the "next scan" step of a cheat search: after the in-game value changes, only the
+    /// addresses that are still plausible are kept. The reads are pipelined in batches. See
+    /// [`Scanner`](struct.Scanner.html) for comparisons against the previously seen values.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use ntr::Connection;
+    ///
+    /// # let connection: Connection = unimplemented!();
+    /// # let pid = ntr::Pid(0);
+    /// let candidates = connection.scan_u32(0x8000000..0x8800000, 1500, pid).expect("io error");
+    /// // ... spend some gold in game ...
+    /// let candidates = connection.scan_refine(&candidates, |gold| gold == 1200, pid)
+    ///     .expect("io error");
+    /// ```
+    pub fn scan_refine<F>(&self, candidates: &[u32], predicate: F, pid: Pid) -> Result<Vec<u32>>
+        where F: Fn(u32) -> bool
+    {
+        let values = self.read_u32_many(candidates, pid)?;
+        Ok(candidates
+               .iter()
+               .zip(values)
+               .filter(|&(_, value)| predicate(value))
+               .map(|(&addr, _)| addr)
+               .collect())
+    }
+
+    /// Reads all of `range` so it can be compared against later, for searching for a value
+    /// whose current number isn't known.
+    ///
+    /// See [`Snapshot`](struct.Snapshot.html).
+    pub fn snapshot_range(&self, range: Range<u32>, pid: Pid) -> Result<Snapshot> {
+        Ok(Snapshot {
+               pid,
+               addr: range.start,
+               data: self.read_range(range, pid)?,
+           })
+    }
+
+    /// Reads the bytes in `range`, which may be empty.
+    fn read_range(&self, range: Range<u32>, pid: Pid) -> Result<Vec<u8>> {
+        if range.start >= range.end {
+            return Ok(Vec::new());
+        }
+        Ok(self.mem_read(range.start, range.end - range.start, pid)?.into_vec())
+    }
+
+    /// Reads the `u32` at each of `addrs`, pipelining the reads in batches.
+    fn read_u32_many(&self, addrs: &[u32], pid: Pid) -> Result<Vec<u32>> {
+        let mut values = Vec::with_capacity(addrs.len());
+        for batch in addrs.chunks(REFINE_BATCH_LEN) {
+            let requests: Vec<(u32, u32)> = batch.iter().map(|&addr| (addr, 4)).collect();
+            for data in self.mem_read_many(&requests, pid)? {
+                values.push(LittleEndian::read_u32(&data));
+            }
+        }
+        Ok(values)
+    }
+}
+
+/// Compares two snapshots of the memory starting at `base_addr`, returning the address, old
+/// value and new value of every byte that differs.
+///
+/// The snapshots would typically be two dumps of the same region taken before and after an
+/// in-game action; the changed addresses narrow down where a value lives even when the value
+/// itself isn't known. If the snapshots have different lengths, only the bytes they both cover
+/// are compared. Addresses past `0xFFFFFFFF` wrap around.
+///
+/// # Examples
+///
+/// ```
+/// use ntr::diff_regions;
+///
+/// let before = [1, 2, 3, 4];
+/// let after = [1, 5, 3, 0];
+/// assert_eq!(diff_regions(0x8000000, &before, &after),
+///            vec![(0x8000001, 2, 5), (0x8000003, 4, 0)]);
+/// ```
+pub fn diff_regions(base_addr: u32, before: &[u8], after: &[u8]) -> Vec<(u32, u8, u8)> {
+    before.iter()
+        .zip(after)
+        .enumerate()
+        .filter(|&(_, (old, new))| old != new)
+        .map(|(i, (&old, &new))| (base_addr.wrapping_add(i as u32), old, new))
+        .collect()
+}
+
+/// A copy of a range of memory, for the "unknown initial value" search.
+///
+/// Taken with [`Connection::snapshot_range`](struct.Connection.html#method.snapshot_range).
+/// Each comparison reads the range again and returns the addresses of the 4-byte aligned `u32`
+/// values that changed in the requested way since the snapshot was taken. The snapshot then
+/// takes on the newly read contents, so the next comparison is against the latest state. The
+/// returned addresses can be handed to
+/// [`Scanner::from_addresses`](struct.Scanner.html#method.from_addresses) to keep narrowing
+/// them down without reading the whole range every time.
+///
+/// # Examples
+///
+/// ```no_run
+/// use ntr::{Connection, Scanner};
+///
+/// # let connection: Connection = unimplemented!();
+/// # let pid = ntr::Pid(0);
+/// let mut snapshot = connection.snapshot_range(0x8000000..0x8800000, pid).expect("io error");
+/// // ... take some damage in game ...
+/// let candidates = snapshot.decreased(&connection).expect("io error");
+/// let mut scanner = Scanner::from_addresses(&connection, &candidates, pid).expect("io error");
+/// ```
+#[derive(Clone, Debug)]
+pub struct Snapshot {
+    pid: Pid,
+    addr: u32,
+    data: Vec<u8>,
+}
+
+impl Snapshot {
+    /// Returns the address the snapshot starts at.
+    pub fn addr(&self) -> u32 {
+        self.addr
+    }
+
+    /// Returns the snapshotted bytes.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Reads the range again and returns the address of every aligned `u32` for which
+    /// `predicate(old, new)` returns `true`, where `old` is the snapshotted value and `new` the
+    /// current one.
+    ///
+    /// The snapshot is replaced by the current contents.
+    pub fn compare<F>(&mut self, connection: &Connection, predicate: F) -> Result<Vec<u32>>
+        where F: Fn(u32, u32) -> bool
+    {
+        let end = self.addr + self.data.len() as u32;
+        let data = connection.read_range(self.addr..end, self.pid)?;
+
+        // the first aligned address at or after the start of the range
+        let skip = (4 - self.addr as usize % 4) % 4;
+        let skip = cmp::min(skip, data.len());
+        let matches = self.data[skip..]
+            .chunks_exact(4)
+            .zip(data[skip..].chunks_exact(4))
+            .enumerate()
+            .filter(|&(_, (old, new))| {
+                predicate(LittleEndian::read_u32(old), LittleEndian::read_u32(new))
+            })
+            .map(|(i, _)| self.addr + (skip + 4 * i) as u32)
+            .collect();
+        self.data = data;
+        Ok(matches)
+    }
+
+    /// Returns the addresses whose value changed since the snapshot.
+    pub fn changed(&mut self, connection: &Connection) -> Result<Vec<u32>> {
+        self.compare(connection, |old, new| new != old)
+    }
+
+    /// Returns the addresses whose value is the same as in the snapshot.
+    pub fn unchanged(&mut self, connection: &Connection) -> Result<Vec<u32>> {
+        self.compare(connection, |old, new| new == old)
+    }
+
+    /// Returns the addresses whose value increased since the snapshot.
+    pub fn increased(&mut self, connection: &Connection) -> Result<Vec<u32>> {
+        self.compare(connection, |old, new| new > old)
+    }
+
+    /// Returns the addresses whose value decreased since the snapshot.
+    pub fn decreased(&mut self, connection: &Connection) -> Result<Vec<u32>> {
+        self.compare(connection, |old, new| new < old)
+    }
+}
+
+/// Returns the addresses a region covers, leaving out the very last byte of the address space
+/// since it can't be the end of a `Range<u32>`.
+fn region_range(region: &MemRegion) -> Range<u32> {
+    region.base..cmp::min(region.end(), u32::MAX as u64) as u32
+}
+
+/// Checks `bytes` against `pattern`, where `None` matches any byte.
+fn matches_pattern(bytes: &[u8], pattern: &[Option<u8>]) -> bool {
+    bytes
+        .iter()
+        .zip(pattern)
+        .all(|(&byte, &expected)| expected.is_none() || expected == Some(byte))
+}
+
+/// Finds the matches of a pattern in memory that's read a chunk at a time.
+struct PatternSearch<'a> {
+    pattern: &'a [Option<u8>],
+    /// The unchecked tail of the chunks pushed so far, so a match can start in one chunk and
+    /// end in the next.
+    window: Vec<u8>,
+    /// The address of the first byte in `window`.
+    window_addr: u32,
+    /// The addresses where a match starts, in order.
+    matches: Vec<u32>,
+}
+
+impl<'a> PatternSearch<'a> {
+    /// Starts a search for `pattern`, which mustn't be empty, in memory starting at `addr`.
+    fn new(pattern: &'a [Option<u8>], addr: u32) -> Self {
+        PatternSearch {
+            pattern,
+            window: Vec::new(),
+            window_addr: addr,
+            matches: Vec::new(),
+        }
+    }
+
+    /// Adds the memory that follows the chunks pushed so far, recording every match that ends
+    /// in it.
+    fn push(&mut self, chunk: &[u8]) {
+        self.window.extend_from_slice(chunk);
+        for (i, bytes) in self.window.windows(self.pattern.len()).enumerate() {
+            if matches_pattern(bytes, self.pattern) {
+                self.matches.push(self.window_addr + i as u32);
+            }
+        }
+        let checked = self.window.len() - cmp::min(self.pattern.len() - 1, self.window.len());
+        self.window.drain(..checked);
+        self.window_addr += checked as u32;
+    }
+}
+
+/// The candidate set of an ongoing `u32` value search.
+///
+/// A `Scanner` remembers each candidate address along with the value it held at the last scan,
+/// so it can narrow the candidates down by how their values changed, even when the value being
+/// searched for isn't known.
+///
+/// # Examples
+///
+/// ```no_run
+/// use ntr::{Connection, Scanner};
+///
+/// # let connection: Connection = unimplemented!();
+/// # let pid = ntr::Pid(0);
+/// let mut scanner = Scanner::new(&connection, 0x8000000..0x8800000, 1500, pid)
+///     .expect("io error");
+/// // ... take some damage in game ...
+/// scanner.decreased(&connection).expect("io error");
+/// // ... wait without taking damage ...
+/// scanner.unchanged(&connection).expect("io error");
+/// println!("{} candidates left", scanner.len());
+/// ```
+#[derive(Clone, Debug)]
+pub struct Scanner {
+    pid: Pid,
+    candidates: Vec<(u32, u32)>,
+}
+
+impl Scanner {
+    /// Starts a search with every address in `range` currently holding `value`.
+    ///
+    /// The range is scanned like [`Connection::scan_u32`](struct.Connection.html#method.scan_u32)
+    /// does.
+    pub fn new(connection: &Connection, range: Range<u32>, value: u32, pid: Pid) -> Result<Self> {
+        let addrs = connection.scan_u32(range, value, pid)?;
+        Ok(Scanner {
+               pid,
+               candidates: addrs.into_iter().map(|addr| (addr, value)).collect(),
+           })
+    }
+
+    /// Starts a search with the given candidate addresses, reading their current values.
+    pub fn from_addresses(connection: &Connection, addrs: &[u32], pid: Pid) -> Result<Self> {
+        let values = connection.read_u32_many(addrs, pid)?;
+        Ok(Scanner {
+               pid,
+               candidates: addrs.iter().cloned().zip(values).collect(),
+           })
+    }
+
+    /// Returns the remaining candidate addresses.
+    pub fn addresses(&self) -> Vec<u32> {
+        self.candidates.iter().map(|&(addr, _)| addr).collect()
+    }
+
+    /// Returns the remaining candidates as (address, last seen value) pairs.
+    pub fn candidates(&self) -> &[(u32, u32)] {
+        &self.candidates
+    }
+
+    /// Returns the number of remaining candidates.
+    pub fn len(&self) -> usize {
+        self.candidates.len()
+    }
+
+    /// Returns `true` if no candidates remain.
+    pub fn is_empty(&self) -> bool {
+        self.candidates.is_empty()
+    }
+
+    /// Re-reads every candidate and keeps those for which `predicate(old, new)` returns `true`,
+    /// where `old` is the value seen at the last scan and `new` is the current value.
+    ///
+    /// The remembered values are updated to the current ones.
+    pub fn refine<F>(&mut self, connection: &Connection, predicate: F) -> Result<()>
+        where F: Fn(u32, u32) -> bool
+    {
+        let addrs = self.addresses();
+        let values = connection.read_u32_many(&addrs, self.pid)?;
+        let candidates = self.candidates
+            .iter()
+            .zip(values)
+            .filter(|&(&(_, old), new)| predicate(old, new))
+            .map(|(&(addr, _), new)| (addr, new))
+            .collect();
+        self.candidates = candidates;
+        Ok(())
+    }
+
+    /// Keeps the candidates that now hold `value`.
+    pub fn equal_to(&mut self, connection: &Connection, value: u32) -> Result<()> {
+        self.refine(connection, |_, new| new == value)
+    }
+
+    /// Keeps the candidates whose value changed since the last scan.
+    pub fn changed(&mut self, connection: &Connection) -> Result<()> {
+        self.refine(connection, |old, new| new != old)
+    }
+
+    /// Keeps the candidates whose value is the same as at the last scan.
+    pub fn unchanged(&mut self, connection: &Connection) -> Result<()> {
+        self.refine(connection, |old, new| new == old)
+    }
+
+    /// Keeps the candidates whose value increased since the last scan.
+    pub fn increased(&mut self, connection: &Connection) -> Result<()> {
+        self.refine(connection, |old, new| new > old)
+    }
+
+    /// Keeps the candidates whose value decreased since the last scan.
+    pub fn decreased(&mut self, connection: &Connection) -> Result<()> {
+        self.refine(connection, |old, new| new < old)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn search(pattern: &[Option<u8>], chunks: &[&[u8]]) -> Vec<u32> {
+        let mut search = PatternSearch::new(pattern, 0x1000);
+        for chunk in chunks {
+            search.push(chunk);
+        }
+        search.matches
+    }
+
+    #[test]
+    fn match_within_chunk() {
+        let pattern = [Some(1), None, Some(3)];
+        assert_eq!(search(&pattern, &[&[0, 1, 2, 3, 0, 1, 9, 3]]), vec![0x1001, 0x1005]);
+    }
+
+    #[test]
+    fn match_straddling_chunk_boundary() {
+        let pattern = [Some(1), Some(2), Some(3), Some(4)];
+        assert_eq!(search(&pattern, &[&[0, 0, 1], &[2, 3, 4, 0]]), vec![0x1002]);
+        assert_eq!(search(&pattern, &[&[0, 1, 2, 3], &[4]]), vec![0x1001]);
+    }
+
+    #[test]
+    fn match_at_end_of_range() {
+        let pattern = [Some(7), Some(8)];
+        assert_eq!(search(&pattern, &[&[0, 0, 0], &[0, 7, 8]]), vec![0x1004]);
+    }
+
+    #[test]
+    fn pattern_longer_than_chunk() {
+        let pattern = [Some(1), Some(2), Some(3), Some(4), Some(5)];
+        assert_eq!(search(&pattern, &[&[9, 1], &[2, 3], &[4, 5], &[9]]), vec![0x1001]);
+        assert_eq!(search(&pattern, &[&[1, 2], &[3, 4]]), vec![]);
+    }
+
+    #[test]
+    fn overlapping_matches() {
+        let pattern = [Some(1), Some(1)];
+        assert_eq!(search(&pattern, &[&[1, 1], &[1, 0, 1], &[1]]), vec![0x1000, 0x1001, 0x1004]);
+    }
+}
@@ -0,0 +1,74 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// Counters describing the traffic on a [`Connection`](struct.Connection.html).
+///
+/// Returned by [`Connection::stats`](struct.Connection.html#method.stats). The counters start
+/// at zero when the connection is opened and keep counting across reconnects. Taking two
+/// snapshots and subtracting gives the rates over the time between them.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ConnectionStats {
+    /// The number of memory read requests sent. Reads larger than the builder's `chunk_size`,
+    /// 64 KiB by default, are split into several requests, and each one counts.
+    pub reads: u64,
+    /// The number of memory write requests sent, counted the same way as `reads`. Writes made
+    /// by `FreezeHandle`s are included.
+    pub writes: u64,
+    /// The number of bytes of memory received in reply to reads.
+    pub bytes_read: u64,
+    /// The number of bytes of memory sent in writes.
+    pub bytes_written: u64,
+    /// The average time between sending a request and receiving its reply, or `None` if no
+    /// reply has arrived yet.
+    ///
+    /// Only requests NTR replies to count, which are memory reads and raw requests; NTR doesn't
+    /// acknowledge writes.
+    pub average_rtt: Option<Duration>,
+}
+
+/// The live counters behind `ConnectionStats`, shared between a connection and its threads.
+#[derive(Debug, Default)]
+pub(crate) struct StatsCounters {
+    reads: AtomicU64,
+    writes: AtomicU64,
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+    rtt_total_nanos: AtomicU64,
+    rtt_samples: AtomicU64,
+}
+
+impl StatsCounters {
+    pub(crate) fn record_read_request(&self) {
+        self.reads.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_write(&self, len: usize) {
+        self.writes.fetch_add(1, Ordering::Relaxed);
+        self.bytes_written.fetch_add(len as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_read_reply(&self, len: usize) {
+        self.bytes_read.fetch_add(len as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_rtt(&self, rtt: Duration) {
+        let nanos = rtt.as_nanos().min(u64::MAX as u128) as u64;
+        self.rtt_total_nanos.fetch_add(nanos, Ordering::Relaxed);
+        self.rtt_samples.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> ConnectionStats {
+        let average_rtt = self.rtt_total_nanos
+            .load(Ordering::Relaxed)
+            .checked_div(self.rtt_samples.load(Ordering::Relaxed))
+            .map(Duration::from_nanos);
+        ConnectionStats {
+            reads: self.reads.load(Ordering::Relaxed),
+            writes: self.writes.load(Ordering::Relaxed),
+            bytes_read: self.bytes_read.load(Ordering::Relaxed),
+            bytes_written: self.bytes_written.load(Ordering::Relaxed),
+            average_rtt,
+        }
+    }
+}
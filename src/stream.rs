@@ -0,0 +1,238 @@
+use std::cmp;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use crate::{CHUNK_LEN, Connection, Pid};
+
+/// The size of the pages 3DS memory is mapped in.
+const PAGE_LEN: u64 = 0x1000;
+
+/// One past the highest address.
+const ADDRESS_SPACE_END: u64 = 1 << 32;
+
+/// A reader over a process's memory, for pointing `std::io` based parsers at the 3DS.
+///
+/// Returned by [`Connection::mem_reader`](struct.Connection.html#method.mem_reader). The
+/// position is an address: seeking to `SeekFrom::Start(addr)` moves to `addr`, and the end of
+/// the stream is the end of the address space. Bytes are fetched with `mem_read` as they're
+/// needed and kept in a buffer, so many small reads in a row only cost one request. The
+/// buffer never extends past the 4 KiB page holding the last byte asked for, so reading up to
+/// the end of a mapped region doesn't touch the unmapped memory after it.
+///
+/// Memory is read lazily, so the buffer can hold stale data if the game changes it between
+/// reads; seeking elsewhere and back doesn't refresh it, but
+/// [`discard_buffer`](#method.discard_buffer) does. Errors from the connection come back as
+/// `io::Error`s; see `impl From<Error> for io::Error`.
+///
+/// # Examples
+///
+/// ```no_run
+/// extern crate byteorder;
+/// # extern crate ntr;
+///
+/// use byteorder::{LittleEndian, ReadBytesExt};
+/// use ntr::Connection;
+/// use std::io::{Seek, SeekFrom};
+///
+/// # fn main() {
+/// # let connection: Connection = unimplemented!();
+/// # let pid = ntr::Pid(0);
+/// let mut reader = connection.mem_reader(0x8001000, pid);
+/// let count = reader.read_u32::<LittleEndian>().expect("io error");
+/// let table = reader.read_u32::<LittleEndian>().expect("io error");
+/// reader.seek(SeekFrom::Start(table as u64)).expect("io error");
+/// let first = reader.read_f32::<LittleEndian>().expect("io error");
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct MemReader<'a> {
+    connection: &'a Connection,
+    pid: Pid,
+    pos: u64,
+    buf: Vec<u8>,
+    buf_addr: u64,
+}
+
+impl<'a> MemReader<'a> {
+    pub(crate) fn new(connection: &'a Connection, addr: u32, pid: Pid) -> Self {
+        MemReader {
+            connection,
+            pid,
+            pos: addr as u64,
+            buf: Vec::new(),
+            buf_addr: 0,
+        }
+    }
+
+    /// Returns the address the next read starts at.
+    pub fn position(&self) -> u64 {
+        self.pos
+    }
+
+    /// Throws away the buffered bytes, so the next read fetches fresh data from the 3DS.
+    pub fn discard_buffer(&mut self) {
+        self.buf.clear();
+    }
+
+    /// Returns the buffered bytes starting at the current position, if there are any.
+    fn buffered(&self) -> &[u8] {
+        let buf_end = self.buf_addr + self.buf.len() as u64;
+        if self.buf_addr <= self.pos && self.pos < buf_end {
+            &self.buf[(self.pos - self.buf_addr) as usize..]
+        } else {
+            &[]
+        }
+    }
+}
+
+impl<'a> Read for MemReader<'a> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        if out.is_empty() || self.pos >= ADDRESS_SPACE_END {
+            return Ok(0);
+        }
+        if self.buffered().is_empty() {
+            // fetch what was asked for, rounded up to the end of its last page
+            let want = cmp::min(out.len() as u64, CHUNK_LEN as u64);
+            let end = cmp::min((self.pos + want + PAGE_LEN - 1) & !(PAGE_LEN - 1),
+                               ADDRESS_SPACE_END);
+            let size = (end - self.pos) as u32;
+            self.buf = self.connection.mem_read(self.pos as u32, size, self.pid)?.into_vec();
+            self.buf_addr = self.pos;
+        }
+
+        let buffered = self.buffered();
+        let len = cmp::min(out.len(), buffered.len());
+        out[..len].copy_from_slice(&buffered[..len]);
+        self.pos += len as u64;
+        Ok(len)
+    }
+}
+
+impl<'a> Seek for MemReader<'a> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.pos = seek_pos(self.pos, pos)?;
+        Ok(self.pos)
+    }
+}
+
+/// A writer into a process's memory, for pointing `std::io` based encoders at the 3DS.
+///
+/// Returned by [`Connection::mem_writer`](struct.Connection.html#method.mem_writer). Like
+/// [`MemReader`](struct.MemReader.html), the position is an address. Consecutive writes are
+/// collected into a buffer and sent with `mem_write` once 64 KiB has built up, when a write
+/// doesn't continue where the last one ended, or on [`flush`](#method.flush), so encoding a
+/// structure field by field costs one request instead of one per field. Seeking doesn't flush
+/// by itself.
+///
+/// Dropping the writer flushes it, but any error doing so is lost; call `flush` first to see
+/// it. As with `mem_write`, NTR doesn't acknowledge writes, so a successful flush only means
+/// the data was sent.
+///
+/// # Examples
+///
+/// ```no_run
+/// extern crate byteorder;
+/// # extern crate ntr;
+///
+/// use byteorder::{LittleEndian, WriteBytesExt};
+/// use ntr::Connection;
+/// use std::io::Write;
+///
+/// # fn main() {
+/// # let connection: Connection = unimplemented!();
+/// # let pid = ntr::Pid(0);
+/// let mut writer = connection.mem_writer(0x8001000, pid);
+/// writer.write_u32::<LittleEndian>(9999).expect("io error");
+/// writer.write_f32::<LittleEndian>(100.0).expect("io error");
+/// writer.flush().expect("io error");
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct MemWriter<'a> {
+    connection: &'a Connection,
+    pid: Pid,
+    pos: u64,
+    buf: Vec<u8>,
+    buf_addr: u64,
+}
+
+impl<'a> MemWriter<'a> {
+    pub(crate) fn new(connection: &'a Connection, addr: u32, pid: Pid) -> Self {
+        MemWriter {
+            connection,
+            pid,
+            pos: addr as u64,
+            buf: Vec::new(),
+            buf_addr: 0,
+        }
+    }
+
+    /// Returns the address the next write starts at.
+    pub fn position(&self) -> u64 {
+        self.pos
+    }
+
+    /// Sends the buffered bytes, if there are any.
+    fn flush_buf(&mut self) -> io::Result<()> {
+        if !self.buf.is_empty() {
+            // cleared even on failure, so a bad address isn't retried on every later write
+            let result = self.connection.mem_write(self.buf_addr as u32, &self.buf, self.pid);
+            self.buf.clear();
+            result?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Write for MemWriter<'a> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if data.is_empty() || self.pos >= ADDRESS_SPACE_END {
+            return Ok(0);
+        }
+        if self.buf_addr + self.buf.len() as u64 != self.pos {
+            self.flush_buf()?;
+        }
+        if self.buf.is_empty() {
+            self.buf_addr = self.pos;
+        }
+
+        let room = cmp::min(CHUNK_LEN as u64 - self.buf.len() as u64,
+                            ADDRESS_SPACE_END - self.pos);
+        let len = cmp::min(data.len() as u64, room) as usize;
+        self.buf.extend_from_slice(&data[..len]);
+        self.pos += len as u64;
+        if self.buf.len() == CHUNK_LEN as usize {
+            self.flush_buf()?;
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.flush_buf()
+    }
+}
+
+impl<'a> Seek for MemWriter<'a> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.pos = seek_pos(self.pos, pos)?;
+        Ok(self.pos)
+    }
+}
+
+impl<'a> Drop for MemWriter<'a> {
+    fn drop(&mut self) {
+        let _ = self.flush_buf();
+    }
+}
+
+/// Works out the address a seek moves to from `current`, where the end of the stream is the end
+/// of the address space.
+fn seek_pos(current: u64, pos: SeekFrom) -> io::Result<u64> {
+    let (base, offset) = match pos {
+        SeekFrom::Start(addr) => return Ok(addr),
+        SeekFrom::Current(offset) => (current, offset),
+        SeekFrom::End(offset) => (ADDRESS_SPACE_END, offset),
+    };
+    base.checked_add_signed(offset).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput,
+                       "invalid seek to a negative or overflowing position")
+    })
+}
@@ -0,0 +1,177 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Range;
+use crate::{FromMemBytes, ToMemBytes};
+
+macro_rules! value_types {
+    ($($variant:ident($ty:ident);)*) => {
+        /// The type of a [`Value`](enum.Value.html), for picking what to read at runtime.
+        #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+        #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+        pub enum ValueType {
+            $(
+                #[doc = concat!("The `", stringify!($ty), "` type.")]
+                $variant,
+            )*
+        }
+
+        /// A value of one of the primitive types, chosen at runtime.
+        ///
+        /// Read with [`Connection::read_value`](struct.Connection.html#method.read_value) and
+        /// written with [`Connection::write_value`](struct.Connection.html#method.write_value).
+        /// This is meant for tools like memory editors where the type to use isn't known until
+        /// the user picks it.
+        #[derive(Clone, Copy, Debug, PartialEq)]
+        #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+        pub enum Value {
+            $(
+                #[doc = concat!("A value of type `", stringify!($ty), "`.")]
+                $variant($ty),
+            )*
+        }
+
+        impl ValueType {
+            /// Every value type, from smallest to largest.
+            pub const ALL: &'static [ValueType] = &[$(ValueType::$variant),*];
+
+            /// Returns the number of bytes a value of this type occupies in memory.
+            pub fn size(self) -> u32 {
+                match self {
+                    $(ValueType::$variant => <$ty as FromMemBytes>::SIZE as u32,)*
+                }
+            }
+
+            /// Decodes a value of this type from `bytes`, which is exactly `size()` bytes long.
+            pub(crate) fn decode(self, bytes: &[u8]) -> Value {
+                match self {
+                    $(
+                        ValueType::$variant => {
+                            Value::$variant(<$ty as FromMemBytes>::from_le_bytes(bytes))
+                        }
+                    )*
+                }
+            }
+        }
+
+        impl Value {
+            /// Returns the type of this value.
+            pub fn value_type(&self) -> ValueType {
+                match *self {
+                    $(Value::$variant(_) => ValueType::$variant,)*
+                }
+            }
+        }
+
+        impl ToMemBytes for Value {
+            fn to_le_bytes(&self) -> Vec<u8> {
+                match *self {
+                    $(Value::$variant(ref value) => ToMemBytes::to_le_bytes(value),)*
+                }
+            }
+        }
+
+        impl fmt::Display for Value {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                match *self {
+                    $(Value::$variant(value) => value.fmt(f),)*
+                }
+            }
+        }
+    }
+}
+
+value_types! {
+    U8(u8);
+    I8(i8);
+    U16(u16);
+    I16(i16);
+    U32(u32);
+    I32(i32);
+    F32(f32);
+    U64(u64);
+    I64(i64);
+    F64(f64);
+}
+
+/// A description of the fields of an in-memory structure, for reading them all at once.
+///
+/// Each field has a name, an offset from the start of the structure and a type. Passing the
+/// layout to [`Connection::read_struct`](struct.Connection.html#method.read_struct) reads the
+/// smallest range covering every field in one go and decodes the fields from it, instead of
+/// making a round trip per field.
+///
+/// # Examples
+///
+/// ```
+/// use ntr::{StructLayout, Value, ValueType};
+///
+/// let layout = StructLayout::new()
+///     .field("hp", 0x10, ValueType::U32)
+///     .field("speed", 0x14, ValueType::F32);
+/// assert_eq!(layout.range(), 0x10..0x18);
+///
+/// // decode takes the bytes of `range()`, so they start at offset 0x10
+/// let fields = layout.decode(&[100, 0, 0, 0, 0, 0, 0x80, 0x3F]);
+/// assert_eq!(fields["hp"], Value::U32(100));
+/// assert_eq!(fields["speed"], Value::F32(1.0));
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StructLayout {
+    fields: Vec<(String, u32, ValueType)>,
+}
+
+impl StructLayout {
+    /// Creates a layout with no fields.
+    pub fn new() -> Self {
+        StructLayout::default()
+    }
+
+    /// Adds a field named `name` of type `ty` at `offset` bytes from the start of the
+    /// structure.
+    ///
+    /// Fields may overlap and don't need to be added in order. If two fields have the same
+    /// name, the one added last shows up in the decoded fields.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the field would end past an offset of `0xFFFFFFFF`.
+    pub fn field<S: Into<String>>(mut self, name: S, offset: u32, ty: ValueType) -> Self {
+        assert!(offset.checked_add(ty.size()).is_some(),
+                "field ends past the largest possible offset");
+        self.fields.push((name.into(), offset, ty));
+        self
+    }
+
+    /// Returns the fields as (name, offset, type) tuples, in the order they were added.
+    pub fn fields(&self) -> &[(String, u32, ValueType)] {
+        &self.fields
+    }
+
+    /// Returns the range of offsets covered by the fields, which is empty if there are none.
+    pub fn range(&self) -> Range<u32> {
+        let start = self.fields.iter().map(|&(_, offset, _)| offset).min().unwrap_or(0);
+        let end = self.fields
+            .iter()
+            .map(|&(_, offset, ty)| offset + ty.size())
+            .max()
+            .unwrap_or(0);
+        start..end
+    }
+
+    /// Decodes every field from `data`, which holds the bytes of [`range()`](#method.range).
+    ///
+    /// # Panics
+    ///
+    /// Panics if `data` is shorter than the range.
+    pub fn decode(&self, data: &[u8]) -> HashMap<String, Value> {
+        let start = self.range().start;
+        self.fields
+            .iter()
+            .map(|&(ref name, offset, ty)| {
+                let field_start = (offset - start) as usize;
+                let bytes = &data[field_start..field_start + ty.size() as usize];
+                (name.clone(), ty.decode(bytes))
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,75 @@
+use crate::link::Link;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use crate::{Error, FromMemBytes, Pid};
+
+/// A value in 3DS memory being watched for changes by a background thread.
+///
+/// Returned by [`Connection::watch`](struct.Connection.html#method.watch). The watch ends when
+/// the handle is dropped or [`stop`](#method.stop) is called, or when the connection is lost.
+#[derive(Debug)]
+pub struct WatchHandle {
+    stop_tx: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl WatchHandle {
+    pub(crate) fn spawn<T, F>(link: Link,
+                              addr: u32,
+                              pid: Pid,
+                              interval: Duration,
+                              read_timeout: Duration,
+                              mut callback: F)
+                              -> Self
+        where T: FromMemBytes + PartialEq + Clone + Send + 'static,
+              F: FnMut(T) + Send + 'static
+    {
+        let (stop_tx, stop_rx) = mpsc::channel::<()>();
+        let thread = thread::spawn(move || {
+            let mut last = None;
+            while link.connected.load(Ordering::SeqCst) {
+                match link.read(addr, T::SIZE as u32, pid, read_timeout) {
+                    Ok(data) => {
+                        let value = T::from_le_bytes(&data);
+                        // the first read only sets the value later ones are compared against
+                        let changed = match last {
+                            Some(ref last) => *last != value,
+                            None => false,
+                        };
+                        if changed {
+                            callback(value.clone());
+                        }
+                        last = Some(value);
+                    }
+                    // a slow or empty response just means this poll is skipped
+                    Err(Error::Timeout) | Err(Error::EmptyResponse) => {}
+                    Err(_) => break,
+                }
+                if let Err(RecvTimeoutError::Disconnected) = stop_rx.recv_timeout(interval) {
+                    break;
+                }
+            }
+        });
+
+        WatchHandle {
+            stop_tx: Some(stop_tx),
+            thread: Some(thread),
+        }
+    }
+
+    /// Stops watching the value and waits for the background thread to exit.
+    pub fn stop(self) {
+        // dropping the handle does the work
+    }
+}
+
+impl Drop for WatchHandle {
+    fn drop(&mut self) {
+        self.stop_tx.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}